@@ -0,0 +1,91 @@
+// compares the rope behind the editor with the plain Vec<String> it replaced
+// run with `cargo bench`
+use std::time::{Duration, Instant};
+
+#[allow(dead_code, unused_imports)]
+#[path = "../src/text.rs"]
+mod text;
+
+use text::{LineRope, TextBuffer};
+
+const LINES: usize = 1_000_000;
+const EDITS: usize = 1_000;
+
+fn get_lines() -> Vec<String> {
+    (0..LINES).map(|i| format!("{:>8} some generated log output for the benchmark", i)).collect()
+}
+
+fn time<T>(mut run: impl FnMut() -> T) -> Duration {
+    let start = Instant::now();
+    std::hint::black_box(run());
+    start.elapsed()
+}
+
+// inserting and removing lines in the middle, like `o` and `dd`
+fn edit_middle<T: TextBuffer>(doc: &mut T) {
+    for i in 0..EDITS {
+        doc.insert(LINES / 2 + i, "inserted".to_string());
+    }
+    for _ in 0..EDITS {
+        doc.remove(LINES / 2);
+    }
+}
+
+// one undo snapshot per change, the way DiffHistory keeps them
+fn edit_with_history<T: TextBuffer + Clone>(doc: &mut T) -> Vec<T> {
+    let mut history = Vec::new();
+    for i in 0..10 {
+        history.push(doc.clone());
+        doc[LINES / 2 + i].push('x');
+    }
+    history
+}
+
+fn report(name: &str, vec_time: Duration, rope_time: Duration) {
+    println!(
+        "{:<28} {:>12.2?} {:>12.2?} {:>8.1}x",
+        name,
+        vec_time,
+        rope_time,
+        vec_time.as_secs_f64() / rope_time.as_secs_f64().max(1e-9),
+    );
+}
+
+fn main() {
+    let lines = get_lines();
+    println!("{} lines", LINES);
+    println!("{:<28} {:>12} {:>12} {:>9}", "", "Vec<String>", "LineRope", "speedup");
+
+    let mut vec_doc = lines.clone();
+    let mut rope_doc = LineRope::from_lines(lines.clone());
+    report(
+        "insert/remove middle x1000",
+        time(|| edit_middle(&mut vec_doc)),
+        time(|| edit_middle(&mut rope_doc)),
+    );
+    report(
+        "delete 100k lines",
+        time(|| vec_doc.remove_lines(LINES / 4..LINES / 4 + 100_000)),
+        time(|| rope_doc.remove_lines(LINES / 4..LINES / 4 + 100_000)),
+    );
+
+    let mut vec_doc = lines.clone();
+    let mut rope_doc = LineRope::from_lines(lines.clone());
+    report(
+        "10 edits with undo history",
+        time(|| edit_with_history(&mut vec_doc)),
+        time(|| edit_with_history(&mut rope_doc)),
+    );
+    let rope_copy = rope_doc.clone();
+    rope_doc[LINES - 1].push('x');
+    report(
+        "compare after one edit",
+        time(|| vec_doc == lines),
+        time(|| rope_doc == rope_copy),
+    );
+    report(
+        "random line access x1M",
+        time(|| (0..LINES).map(|i| vec_doc[(i * 7919) % LINES].len()).sum::<usize>()),
+        time(|| (0..LINES).map(|i| rope_doc[(i * 7919) % LINES].len()).sum::<usize>()),
+    );
+}
@@ -0,0 +1,383 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use crate::diffhist;
+use crate::error;
+use crate::helper;
+use crate::language;
+use crate::swap;
+use crate::syntax;
+use crate::text::{LineRope, TextBuffer};
+use crate::tree;
+
+// files larger than this show their first lines right away and load the rest in the background
+const STREAM_SIZE: u64 = 16 * 1024 * 1024;
+// and files larger than this stay read only once loaded
+const VIEW_SIZE: u64 = 256 * 1024 * 1024;
+// the buffer read from stdin with the - argument
+pub const STDIN_NAME: &str = "[stdin]";
+// the empty buffer opened when no files are given, :w name gives it a file
+pub const NO_NAME: &str = "[No Name]";
+pub const LOAD_DELAY: Duration = Duration::from_millis(50);
+const FIRST_LINES: usize = 10_000;
+const LOAD_BATCH: usize = 64 * 1024;
+
+pub struct Buffer {
+    pub file_name: String,
+    pub file_data: LineRope,
+    pub cursor_x: usize,
+    pub cursor_y: usize,
+    pub window_line_x: usize,
+    pub window_line_y: usize,
+    pub search_string: String,
+    pub searching: bool,
+    pub diff_history: diffhist::DiffHistory,
+    pub modified: bool,
+    pub scratch: bool,
+    pub line_ending: String,
+    pub swap_hash: u64,
+    pub saved_data: LineRope,
+    pub disk_time: Option<SystemTime>,
+    pub external_data: Option<Vec<String>>,
+    pub readonly: bool,
+    // recovered from a swap file or merged with the disk and not written yet, so autosave leaves it alone until :w
+    pub awaiting_write: bool,
+    pub last_visual: (usize, usize),
+    pub loading: Option<Receiver<io::Result<Vec<String>>>>,
+    pub language: Option<&'static language::Language>,
+    pub highlights: syntax::Highlights,
+    pub tree: tree::SyntaxTree,
+}
+
+impl Buffer {
+    pub fn new(file_name: &str, mut file_data: Vec<String>) -> Self {
+        if file_data.is_empty() {
+            file_data.insert(0, "".to_string());
+        }
+        let file_data = LineRope::from(file_data);
+        Buffer {
+            file_name: file_name.to_string(),
+            diff_history: diffhist::DiffHistory::new(file_data.clone()),
+            cursor_x: 0,
+            cursor_y: 0,
+            window_line_x: 0,
+            window_line_y: 0,
+            search_string: "".to_string(),
+            searching: false,
+            modified: false,
+            scratch: false,
+            line_ending: "\n".to_string(),
+            swap_hash: swap::hash_data(&file_data),
+            saved_data: file_data.clone(),
+            disk_time: None,
+            external_data: None,
+            readonly: false,
+            awaiting_write: false,
+            last_visual: (0, 0),
+            loading: None,
+            language: language::detect(file_name, &file_data),
+            highlights: syntax::Highlights::new(),
+            tree: tree::SyntaxTree::new(),
+            file_data,
+        }
+    }
+
+    // a path that does not exist yet opens empty and is created by the first write
+    pub fn open(file_name: &str) -> io::Result<Self> {
+        let metadata = match fs::metadata(file_name) {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Buffer::new(file_name, Vec::new())),
+            Err(err) => return Err(err),
+        };
+        if metadata.is_dir() {
+            return Err(io::Error::other("is a directory"));
+        }
+        if metadata.len() > STREAM_SIZE {
+            return Buffer::open_streaming(file_name);
+        }
+        let mut buffer = Buffer::new(file_name, helper::get_file_data(file_name)?);
+        buffer.line_ending = helper::get_line_ending(file_name)?;
+        buffer.disk_time = get_disk_time(file_name);
+        buffer.readonly = !is_writable(file_name);
+        Ok(buffer)
+    }
+
+    // reads the first screens now and sends the rest from a thread in batches, ending with a read error if any
+    fn open_streaming(file_name: &str) -> io::Result<Self> {
+        let mut lines = BufReader::new(fs::File::open(file_name)?).lines();
+        let first_lines = lines.by_ref().take(FIRST_LINES).collect::<io::Result<Vec<String>>>()?;
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || loop {
+            let mut batch = Vec::new();
+            let mut read_error = None;
+            for line in lines.by_ref().take(LOAD_BATCH) {
+                match line {
+                    Ok(line) => batch.push(line),
+                    Err(err) => {
+                        read_error = Some(err);
+                        break;
+                    }
+                }
+            }
+            let done = batch.is_empty() || read_error.is_some();
+            if !batch.is_empty() && sender.send(Ok(batch)).is_err() {
+                break;
+            }
+            if let Some(err) = read_error {
+                let _ = sender.send(Err(err));
+            }
+            if done {
+                break;
+            }
+        });
+        let mut buffer = Buffer::new(file_name, first_lines);
+        buffer.line_ending = helper::get_line_ending(file_name)?;
+        buffer.readonly = !is_writable(file_name);
+        buffer.loading = Some(receiver);
+        Ok(buffer)
+    }
+
+    // appends whatever the loading thread has read so far, returns whether anything changed; a file that could
+    // not be read to the end stays read only, so writing it does not cut off the rest
+    pub fn poll_loading(&mut self) -> error::Result<bool> {
+        let mut changed = false;
+        let mut load_error = None;
+        while let Some(receiver) = &self.loading {
+            match receiver.try_recv() {
+                Ok(Ok(lines)) => {
+                    self.file_data.append_lines(lines);
+                    changed = true;
+                }
+                Ok(Err(err)) => {
+                    self.loading = None;
+                    self.readonly = true;
+                    let context = format!("Stopped loading {} after line {}, the buffer is read only", self.file_name, self.file_data.len());
+                    load_error = Some(error::EditorError::Io(context, err));
+                    changed = true;
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.loading = None;
+                    self.readonly |= fs::metadata(&self.file_name).map(|m| m.len() > VIEW_SIZE).unwrap_or(false);
+                    self.swap_hash = swap::hash_data(&self.file_data);
+                    self.disk_time = get_disk_time(&self.file_name);
+                    changed = true;
+                }
+            }
+        }
+        if changed {
+            // the loaded lines are part of the file, not an edit
+            self.diff_history = diffhist::DiffHistory::new(self.file_data.clone());
+            self.saved_data = self.file_data.clone();
+        }
+        match load_error {
+            Some(err) => Err(err),
+            None => Ok(changed),
+        }
+    }
+
+    pub fn is_new(&self) -> bool {
+        !self.scratch && fs::metadata(&self.file_name).is_err()
+    }
+
+    // a buffer is also read only until it has finished loading
+    pub fn is_readonly(&self) -> bool {
+        self.readonly || self.loading.is_some()
+    }
+
+    // checked before a change is made, so a read only buffer is never touched
+    pub fn check_editable(&self) -> error::Result<()> {
+        if self.is_readonly() {
+            return Err(error::EditorError::ReadOnly(self.file_name.clone()));
+        }
+        Ok(())
+    }
+
+    // a buffer that is never written to disk, like the quickfix list
+    pub fn scratch(name: &str, file_data: Vec<String>) -> Self {
+        let mut buffer = Buffer::new(name, file_data);
+        buffer.scratch = true;
+        buffer
+    }
+
+    pub fn set_data(&mut self, file_data: Vec<String>) {
+        *self = Buffer {
+            scratch: self.scratch,
+            line_ending: self.line_ending.clone(),
+            ..Buffer::new(&self.file_name, file_data)
+        };
+    }
+
+    // ends the current undo step, writing to disk with autosave if anything changed
+    pub fn snapshot(&mut self, autosave: bool) -> error::Result<()> {
+        if self.diff_history.make_change(&self.file_data) && !self.scratch {
+            self.modified = true;
+        }
+        if autosave && self.modified && !self.awaiting_write && self.external_data.is_none() {
+            self.write()?;
+        }
+        Ok(())
+    }
+
+    // :w name; a buffer with no file of its own, like [stdin], takes the name, any other writes a copy there
+    pub fn write_as(&mut self, file_name: &str, force: bool) -> error::Result<()> {
+        if file_name == self.file_name {
+            return self.write();
+        }
+        if !force && Path::new(file_name).exists() {
+            return Err(error::EditorError::FileExists(file_name.to_string()));
+        }
+        if self.scratch {
+            self.file_name = file_name.to_string();
+            self.scratch = false;
+            return self.write();
+        }
+        helper::save_to_file_no_snapshot(&self.file_data, file_name, &self.line_ending)
+    }
+
+    // creates the missing parent directories first, for :w ++p
+    pub fn write_creating_dirs(&mut self) -> error::Result<()> {
+        if let Some(parent) = Path::new(&self.file_name).parent() {
+            if !parent.as_os_str().is_empty() {
+                fs::create_dir_all(parent)
+                    .map_err(|err| error::EditorError::Io(format!("Failed to create {}", parent.display()), err))?;
+            }
+        }
+        self.write()
+    }
+
+    pub fn write(&mut self) -> error::Result<()> {
+        if self.scratch {
+            return Err(error::EditorError::NoFileName);
+        }
+        self.check_editable()?;
+        // never overwrite a change made by another program without asking
+        self.check_disk();
+        if self.external_data.is_some() {
+            self.modified = true;
+            return Err(error::EditorError::Conflict(self.file_name.clone()));
+        }
+        let result = helper::save_to_file_no_snapshot(&self.file_data, &self.file_name, &self.line_ending);
+        self.modified = result.is_err();
+        result?;
+        self.swap_hash = swap::hash_data(&self.file_data);
+        swap::delete_swap_file(&self.file_name);
+        self.awaiting_write = false;
+        self.saved_data = self.file_data.clone();
+        self.disk_time = get_disk_time(&self.file_name);
+        Ok(())
+    }
+
+    // reloads a clean buffer whose file changed on disk, or keeps the new contents for :reload and :merge
+    pub fn check_disk(&mut self) -> Option<String> {
+        if self.scratch || self.disk_time.is_none() || get_disk_time(&self.file_name) == self.disk_time {
+            return None;
+        }
+        let disk_data = helper::get_file_data(&self.file_name).ok()?;
+        self.disk_time = get_disk_time(&self.file_name);
+        let is_blank = self.file_data.len() == 1 && self.file_data[0].is_empty();
+        if self.file_data == disk_data || (disk_data.is_empty() && is_blank) {
+            self.saved_data = self.file_data.clone();
+            self.external_data = None;
+            return None;
+        }
+        if !self.modified && self.file_data == self.saved_data {
+            self.reload(disk_data);
+            return Some(format!("{} reloaded from disk", self.file_name));
+        }
+        self.external_data = Some(disk_data);
+        Some(error::EditorError::Conflict(self.file_name.clone()).to_string())
+    }
+
+    // replaces the contents as one undoable change
+    pub fn reload(&mut self, mut disk_data: Vec<String>) {
+        if disk_data.is_empty() {
+            disk_data.push("".to_string());
+        }
+        let disk_data = LineRope::from(disk_data);
+        self.diff_history.make_change(&disk_data);
+        self.saved_data = disk_data.clone();
+        self.swap_hash = swap::hash_data(&disk_data);
+        self.file_data = disk_data;
+        self.external_data = None;
+        self.modified = false;
+    }
+
+    // the swap file's text as one change to undo; its swap file stays until the recovery is written
+    pub fn recover(&mut self, mut swap_data: Vec<String>) {
+        if swap_data.is_empty() {
+            swap_data.push("".to_string());
+        }
+        self.file_data = LineRope::from(swap_data);
+        self.diff_history.make_change(&self.file_data);
+        self.modified = true;
+        self.awaiting_write = true;
+    }
+
+    pub fn keep(&mut self) -> error::Result<()> {
+        if let Some(disk_data) = self.external_data.take() {
+            self.saved_data = LineRope::from(disk_data);
+        }
+        self.write()
+    }
+
+    // returns how many conflicts were marked
+    pub fn merge(&mut self) -> Option<usize> {
+        let disk_data = self.external_data.take()?;
+        let (merged, conflicts) = diffhist::merge(&self.saved_data.to_vec(), &self.file_data.to_vec(), &disk_data);
+        self.saved_data = LineRope::from(disk_data);
+        self.file_data = LineRope::from(merged);
+        // the merge is one change to undo, and its conflicts are for the user to resolve before it is written
+        self.diff_history.make_change(&self.file_data);
+        self.modified = true;
+        self.awaiting_write = true;
+        Some(conflicts)
+    }
+}
+
+pub fn get_disk_time(file_name: &str) -> Option<SystemTime> {
+    fs::metadata(file_name).and_then(|metadata| metadata.modified()).ok()
+}
+
+// opening for writing without truncating changes nothing, and also works for root and read only mounts
+pub fn is_writable(file_name: &str) -> bool {
+    fs::OpenOptions::new().write(true).open(file_name).is_ok()
+}
+
+pub fn check_external_changes(buffers: &mut [Buffer], message: &mut String) {
+    for buffer in buffers.iter_mut() {
+        if let Some(change) = buffer.check_disk() {
+            *message = change;
+        }
+    }
+}
+
+pub fn poll_loading(buffers: &mut [Buffer], message: &mut String) -> bool {
+    let mut changed = false;
+    for buffer in buffers.iter_mut() {
+        changed |= error::report(buffer.poll_loading(), message).unwrap_or(true);
+    }
+    changed
+}
+
+pub fn find_buffer(buffers: &[Buffer], file_name: &str) -> Option<usize> {
+    buffers.iter().position(|buffer| buffer.file_name == file_name)
+}
+
+pub fn list_buffers(buffers: &[Buffer], current_buffer: usize) -> String {
+    let mut lines = Vec::new();
+    for (i, buffer) in buffers.iter().enumerate() {
+        lines.push(format!(
+            "{:3} {}{} \"{}\" line {}",
+            i + 1,
+            if i == current_buffer { "%" } else { " " },
+            if buffer.modified { "+" } else { " " },
+            buffer.file_name,
+            buffer.cursor_y + 1,
+        ));
+    }
+    lines.join("\n")
+}
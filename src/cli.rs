@@ -0,0 +1,160 @@
+use std::path::Path;
+
+pub const USAGE: &str = "Usage: editor [options] [file ...]
+
+  file            open a file
+  file:line:col   open a file at a line and column
+  -               read the text from stdin
+  +N              start the first file at line N, + for the last line
+  +/pattern       start the first file at the first match of pattern
+  -c command      run a : command once the files are open, can be repeated
+  -R, --readonly  open the files read only
+  --stdout        print the stdin buffer, or else the current one, on exit;
+                  the default when stdout is not a terminal
+  --clean         start without the user config
+  -h, --help      show this help and exit
+  --version       show the version and exit";
+
+pub struct FileArg {
+    pub name: String,
+    pub line: Option<usize>,
+    pub col: Option<usize>,
+}
+
+#[derive(Default)]
+pub struct Args {
+    pub files: Vec<FileArg>,
+    pub readonly: bool,
+    pub stdout: bool,
+    pub clean: bool,
+    pub commands: Vec<String>,
+    pub search: Option<String>,
+}
+
+// what to do instead of editing, like printing the help
+pub enum Action {
+    Edit(Args),
+    Print(String),
+}
+
+pub fn get_version() -> String {
+    format!("editor {}", env!("CARGO_PKG_VERSION"))
+}
+
+// "src/main.rs:10:5" from compiler output, unless a file with that whole name exists
+pub fn parse_file_arg(arg: &str) -> FileArg {
+    let whole = FileArg { name: arg.to_string(), line: None, col: None };
+    if Path::new(arg).exists() {
+        return whole;
+    }
+    let Some((rest, last)) = arg.trim_end_matches(':').rsplit_once(':') else { return whole };
+    let Ok(last) = last.parse() else { return whole };
+    if let Some((name, line)) = rest.rsplit_once(':') {
+        if let (false, Ok(line)) = (name.is_empty(), line.parse()) {
+            return FileArg { name: name.to_string(), line: Some(line), col: Some(last) };
+        }
+    }
+    if rest.is_empty() {
+        return whole;
+    }
+    FileArg { name: rest.to_string(), line: Some(last), col: None }
+}
+
+pub fn parse_args(args: &[String]) -> Result<Action, String> {
+    let mut parsed = Args::default();
+    let mut start_line = None;
+    let mut only_files = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if only_files || arg == "-" || !(arg.starts_with('-') || arg.starts_with('+')) {
+            parsed.files.push(if arg == "-" { FileArg { name: arg.clone(), line: None, col: None } } else { parse_file_arg(arg) });
+        } else if arg == "--" {
+            only_files = true;
+        } else if arg == "-h" || arg == "--help" {
+            return Ok(Action::Print(USAGE.to_string()));
+        } else if arg == "--version" {
+            return Ok(Action::Print(get_version()));
+        } else if arg == "-R" || arg == "--readonly" {
+            parsed.readonly = true;
+        } else if arg == "--stdout" {
+            parsed.stdout = true;
+        } else if arg == "--clean" {
+            parsed.clean = true;
+        } else if arg == "-c" {
+            match args.next() {
+                Some(command) => parsed.commands.push(command.trim_start_matches(':').to_string()),
+                None => return Err("-c needs a command".to_string()),
+            }
+        } else if arg == "+" {
+            start_line = Some(usize::MAX);
+        } else if let Some(pattern) = arg.strip_prefix("+/") {
+            parsed.search = Some(pattern.to_string());
+        } else if let Some(Ok(line)) = arg.strip_prefix('+').map(|line| line.parse::<usize>()) {
+            start_line = Some(line);
+        } else {
+            return Err(format!("Unknown option: {}", arg));
+        }
+    }
+    if let (Some(line), Some(file)) = (start_line, parsed.files.first_mut()) {
+        file.line = Some(line);
+        file.col = None;
+    }
+    Ok(Action::Edit(parsed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Action, String> {
+        parse_args(&args.iter().map(|arg| arg.to_string()).collect::<Vec<String>>())
+    }
+
+    fn parse_edit(args: &[&str]) -> Args {
+        match parse(args) {
+            Ok(Action::Edit(args)) => args,
+            Ok(Action::Print(text)) => panic!("printed {}", text),
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    fn files(args: &Args) -> Vec<(&str, Option<usize>, Option<usize>)> {
+        args.files.iter().map(|file| (file.name.as_str(), file.line, file.col)).collect()
+    }
+
+    #[test]
+    fn parses_file_positions() {
+        let args = parse_edit(&["no/such/file.rs:10:5", "other.rs:3", "plain.rs", "-", "trailing.rs:7:"]);
+        assert_eq!(files(&args), vec![
+            ("no/such/file.rs", Some(10), Some(5)),
+            ("other.rs", Some(3), None),
+            ("plain.rs", None, None),
+            ("-", None, None),
+            ("trailing.rs", Some(7), None),
+        ]);
+        assert_eq!(files(&parse_edit(&[":3", "a:b"])), vec![(":3", None, None), ("a:b", None, None)]);
+        // a file that exists keeps its whole name
+        assert_eq!(files(&parse_edit(&["Cargo.toml"])), vec![("Cargo.toml", None, None)]);
+    }
+
+    #[test]
+    fn parses_options() {
+        let args = parse_edit(&["-R", "--stdout", "--clean", "-c", ":set number", "-c", "q", "+/fn main", "a.rs"]);
+        assert!(args.readonly && args.stdout && args.clean);
+        assert_eq!(args.commands, vec!["set number", "q"]);
+        assert_eq!(args.search.as_deref(), Some("fn main"));
+        assert_eq!(files(&args), vec![("a.rs", None, None)]);
+        assert_eq!(files(&parse_edit(&["+12", "a.rs:3:4", "b.rs"])), vec![("a.rs", Some(12), None), ("b.rs", None, None)]);
+        assert_eq!(files(&parse_edit(&["a.rs", "+"])), vec![("a.rs", Some(usize::MAX), None)]);
+        assert_eq!(files(&parse_edit(&["--", "-R", "+3"])), vec![("-R", None, None), ("+3", None, None)]);
+    }
+
+    #[test]
+    fn reports_bad_options() {
+        assert_eq!(parse(&["--nope"]).err(), Some("Unknown option: --nope".to_string()));
+        assert_eq!(parse(&["+x"]).err(), Some("Unknown option: +x".to_string()));
+        assert_eq!(parse(&["-c"]).err(), Some("-c needs a command".to_string()));
+        assert!(matches!(parse(&["a.rs", "--help"]), Ok(Action::Print(text)) if text == USAGE));
+        assert!(matches!(parse(&["--version"]), Ok(Action::Print(text)) if text == get_version()));
+    }
+}
@@ -0,0 +1,435 @@
+use std::path::Path;
+use crate::buffer;
+use crate::error;
+use crate::explorer;
+use crate::helper;
+use crate::keymap;
+use crate::language;
+use crate::options;
+use crate::quickfix;
+use crate::swap;
+use crate::tab;
+use crate::text::TextBuffer;
+use crate::window;
+
+// switches the window to the buffer for file_name, opening it if needed
+// returns the buffer the window switched to, None if the file could not be opened
+pub fn edit_file(file_name: &str, buffers: &mut Vec<buffer::Buffer>, window: &mut window::Window, message: &mut String) -> Option<usize> {
+    if let Some(index) = buffer::find_buffer(buffers, file_name) {
+        window.set_buffer(buffers, index);
+        return Some(index);
+    }
+    match buffer::Buffer::open(file_name) {
+        Ok(new_buffer) => {
+            if new_buffer.is_new() {
+                *message = format!("\"{}\" [New]", file_name);
+            }
+            swap::check_swap_file(file_name, &new_buffer.file_data, message);
+            buffers.push(new_buffer);
+            let index = buffers.len() - 1;
+            window.set_buffer(buffers, index);
+            Some(index)
+        }
+        Err(err) => {
+            *message = format!("Failed to open {}: {}", file_name, err);
+            None
+        }
+    }
+}
+
+// the editor state a : command can change
+pub struct CommandContext<'a> {
+    pub buffers: &'a mut Vec<buffer::Buffer>,
+    pub tabs: &'a mut tab::Tabs,
+    pub quickfix: &'a mut quickfix::Quickfix,
+    pub explorer: &'a mut explorer::Explorer,
+    pub options: &'a mut options::Options,
+    pub keymap: &'a mut keymap::Keymap,
+    pub quit: &'a mut bool,
+}
+
+pub fn run_command(command_string: &str, context: CommandContext, message_history: &[String], message: &mut String) {
+    let CommandContext { buffers, tabs, quickfix, explorer, options, keymap, quit } = context;
+    let window = tabs.current().current();
+    let (cursor_y, current_buffer) = (window.cursor_y, window.buffer);
+    let file_data = &buffers[current_buffer].file_data;
+    let (range, command_string) =
+        match parse_range(command_string.trim(), cursor_y, file_data.len(), buffers[current_buffer].last_visual) {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                *message = err;
+                return;
+            }
+        };
+    let command_string = command_string.trim();
+    if let Some(shell_command) = command_string.strip_prefix('!') {
+        match range {
+            Some(_) if error::report(buffers[current_buffer].check_editable(), message).is_none() => (),
+            Some((start, end)) => filter_lines(start, end, shell_command.trim(), &mut buffers[current_buffer], tabs.current().current(), message),
+            None => {
+                if let Some(output) = error::report(helper::run_shell(shell_command.trim(), ""), message) {
+                    *message = output.trim_end().to_string();
+                }
+            }
+        }
+        return;
+    }
+    let (command, arg) = match command_string.split_once(' ') {
+        Some((command, arg)) => (command, arg.trim()),
+        None => (command_string, ""),
+    };
+    if command.is_empty() {
+        // a line number on its own goes to that line
+        if let Some((_, end)) = range {
+            let window = tabs.current().current();
+            window.cursor_y = helper::reset_cursor_end_file(buffers[window.buffer].file_data.len(), end);
+            window.cursor_x = 0;
+        }
+        return;
+    }
+    if range.is_some() && command != "r" && command != "read" {
+        *message = format!("No range allowed: {}", command);
+        return;
+    }
+    // the commands that change the text refuse a read only buffer before they run
+    if matches!(command, "r" | "read" | "merge" | "recover") && error::report(buffers[current_buffer].check_editable(), message).is_none() {
+        return;
+    }
+    let layout = tabs.current();
+    let buffer_count = buffers.len();
+    if command == "r" || command == "read" {
+        let line = range.map(|(_, end)| end).unwrap_or(cursor_y);
+        let lines = match arg.strip_prefix('!') {
+            Some(shell_command) => error::report(helper::run_shell(shell_command.trim(), ""), message)
+                .map(|output| output.lines().map(|line| line.to_string()).collect()),
+            None if arg.is_empty() => {
+                *message = "No file name".to_string();
+                None
+            }
+            None => match helper::get_file_data(arg) {
+                Ok(lines) => Some(lines),
+                Err(err) => {
+                    *message = format!("Failed to read {}: {}", arg, err);
+                    None
+                }
+            },
+        };
+        if let Some(lines) = lines {
+            insert_lines(line + 1, lines, &mut buffers[current_buffer], layout.current());
+        }
+    } else if command == "w" && arg == "++p" {
+        error::report(buffers[current_buffer].write_creating_dirs(), message);
+    } else if command == "w" || command == "w!" {
+        let buffer = &mut buffers[current_buffer];
+        let result = if arg.is_empty() { buffer.write() } else { buffer.write_as(arg, command == "w!") };
+        error::report(result, message);
+    } else if command == "q" || command == "quit" {
+        // every window goes at once, so any unsaved buffer stops it
+        match buffers.iter().find(|buffer| buffer.modified && !buffer.scratch) {
+            Some(buffer) => *message = error::EditorError::Unsaved(buffer.file_name.clone()).to_string(),
+            None => *quit = true,
+        }
+    } else if command == "q!" || command == "quit!" {
+        *quit = true;
+    } else if command == "e" {
+        if arg.is_empty() {
+            *message = "No file name".to_string();
+        } else if Path::new(arg).is_dir() {
+            explorer::open_dir(arg, explorer, buffers, layout.current());
+        } else {
+            edit_file(arg, buffers, layout.current(), message);
+        }
+    } else if command == "view" || command == "vie" {
+        // only the buffer that was opened becomes read only, not the one left behind when opening failed
+        let opened = if arg.is_empty() { Some(current_buffer) } else { edit_file(arg, buffers, layout.current(), message) };
+        if let Some(index) = opened {
+            buffers[index].readonly = true;
+        }
+    } else if command == "bn" {
+        layout.current().set_buffer(buffers, (current_buffer + 1) % buffer_count);
+    } else if command == "bp" {
+        layout.current().set_buffer(buffers, (current_buffer + buffer_count - 1) % buffer_count);
+    } else if command == "b" {
+        match arg.parse::<usize>() {
+            Ok(number) if number >= 1 && number <= buffer_count => layout.current().set_buffer(buffers, number - 1),
+            _ => *message = format!("Buffer {} does not exist", arg),
+        }
+    } else if command == "ls" {
+        layout.current().save_position(buffers);
+        *message = buffer::list_buffers(buffers, current_buffer);
+    } else if command == "messages" || command == "mes" {
+        *message = message_history.join("\n");
+    } else if command == "checktime" {
+        buffer::check_external_changes(buffers, message);
+    } else if command == "reload" || command == "e!" {
+        let buffer = &mut buffers[current_buffer];
+        buffer.check_disk();
+        let disk_data = buffer.external_data.take().map(Ok).unwrap_or_else(|| helper::get_file_data(&buffer.file_name));
+        match disk_data {
+            Ok(disk_data) => buffer.reload(disk_data),
+            Err(err) => *message = format!("Failed to reload {}: {}", buffer.file_name, err),
+        }
+    } else if command == "keep" {
+        error::report(buffers[current_buffer].keep(), message);
+    } else if command == "merge" {
+        match buffers[current_buffer].merge() {
+            Some(0) => *message = "Merged without conflicts, :w to keep it".to_string(),
+            Some(conflicts) => *message = format!("Merged with {} conflicts, :w to keep it", conflicts),
+            None => *message = "No changes on disk to merge".to_string(),
+        }
+    } else if command == "recover" {
+        let buffer = &mut buffers[current_buffer];
+        match swap::read_swap_file(&buffer.file_name) {
+            Some((_, swap_data)) => {
+                buffer.recover(swap_data);
+                *message = format!("Recovered {} from its swap file, :w to keep it", buffer.file_name);
+            }
+            None => *message = format!("No swap file for {}", buffer.file_name),
+        }
+    } else if command == "diffswap" {
+        let buffer = &buffers[current_buffer];
+        match swap::get_swap_diff(&buffer.file_name, &buffer.file_data) {
+            Some(lines) => {
+                match buffer::find_buffer(buffers, swap::SWAP_DIFF_NAME) {
+                    Some(index) => buffers[index].set_data(lines),
+                    None => buffers.push(buffer::Buffer::scratch(swap::SWAP_DIFF_NAME, lines)),
+                }
+                let index = buffer::find_buffer(buffers, swap::SWAP_DIFF_NAME).unwrap_or(0);
+                layout.split(true);
+                layout.current().set_buffer(buffers, index);
+            }
+            None => *message = format!("No swap file for {}", buffer.file_name),
+        }
+    } else if command == "delswap" {
+        swap::delete_swap_file(&buffers[current_buffer].file_name);
+    } else if command == "sp" || command == "vsp" {
+        layout.split(command == "vsp");
+        if !arg.is_empty() {
+            edit_file(arg, buffers, layout.current(), message);
+        }
+    } else if command == "close" {
+        if !layout.close() {
+            *message = "Cannot close last window".to_string();
+        }
+    } else if command == "only" {
+        layout.only();
+    } else if command == "tabnew" || command == "tabe" {
+        tabs.new_tab();
+        if !arg.is_empty() {
+            edit_file(arg, buffers, tabs.current().current(), message);
+        }
+    } else if command == "tabclose" {
+        if !tabs.close() {
+            *message = "Cannot close last tab page".to_string();
+        }
+    } else if command == "grep" || command == "vimgrep" || command == "vim" {
+        let pattern = quickfix::parse_pattern(arg);
+        if pattern.is_empty() {
+            *message = "No pattern".to_string();
+            return;
+        }
+        let entries = quickfix::grep_project(".", pattern);
+        if entries.is_empty() {
+            *message = format!("No match: {}", pattern);
+            return;
+        }
+        quickfix::set_entries(quickfix, entries, buffers);
+        quickfix::jump_to_entry(quickfix, 0, buffers, tabs.current(), message);
+    } else if command == "make" {
+        let output = match quickfix::run_make(&options.makeprg, arg) {
+            Ok(output) => output,
+            Err(err) => {
+                *message = err;
+                return;
+            }
+        };
+        let entries = quickfix::parse_errors(&output, &options.errorformat);
+        let count = entries.len();
+        quickfix::set_entries(quickfix, entries, buffers);
+        if count == 0 {
+            *message = format!("{}: no errors", options.makeprg);
+        } else {
+            quickfix::jump_to_entry(quickfix, 0, buffers, tabs.current(), message);
+        }
+    } else if let Some(&(_, modes, recursive)) = keymap::MAP_COMMANDS.iter().find(|(name, _, _)| *name == command) {
+        match keymap::run_map_command(keymap, modes, recursive, arg, &options.leader) {
+            Ok(lines) => *message = lines,
+            Err(err) => *message = err,
+        }
+    } else if let Some(&(_, modes)) = keymap::UNMAP_COMMANDS.iter().find(|(name, _)| *name == command) {
+        if let Err(err) = keymap::run_unmap_command(keymap, modes, arg, &options.leader) {
+            *message = err;
+        }
+    } else if command == "set" || command == "se" {
+        if arg.is_empty() || arg == "all" {
+            let values: Vec<String> = options::OPTION_NAMES
+                .iter()
+                .map(|name| format!("{}={}", name, options.get(name).unwrap_or_default()))
+                .collect();
+            *message = values.join("\n");
+        } else if arg == "readonly" || arg == "ro" || arg == "noreadonly" || arg == "noro" {
+            // readonly and filetype belong to the buffer, not the options
+            buffers[current_buffer].readonly = !arg.starts_with("no");
+        } else if arg == "filetype?" || arg == "ft?" {
+            *message = format!("filetype={}", language::get_name(buffers[current_buffer].language));
+        } else if let Some(name) = arg.strip_prefix("filetype=").or_else(|| arg.strip_prefix("ft=")) {
+            match language::get_language(name) {
+                Some(language) => buffers[current_buffer].language = Some(language),
+                None if name.is_empty() => buffers[current_buffer].language = None,
+                None => *message = format!("Unknown filetype {}", name),
+            }
+        } else if let Some(name) = arg.strip_suffix('?') {
+            *message = match options.get(name) {
+                Some(value) => format!("{}={}", name, value),
+                None => options::get_unknown_message(name),
+            };
+        } else if let Err(err) = options::parse_set_arg(arg).and_then(|(name, value)| options.set(&name, value)) {
+            *message = err;
+        }
+    } else if command == "cn" || command == "cnext" {
+        if quickfix.current + 1 < quickfix.entries.len() {
+            quickfix::jump_to_entry(quickfix, quickfix.current + 1, buffers, layout, message);
+        } else {
+            *message = "No more items".to_string();
+        }
+    } else if command == "cp" || command == "cprev" || command == "cprevious" {
+        if quickfix.current > 0 {
+            quickfix::jump_to_entry(quickfix, quickfix.current - 1, buffers, layout, message);
+        } else {
+            *message = "No more items".to_string();
+        }
+    } else if command == "cc" {
+        let entry = arg.parse::<usize>().unwrap_or(quickfix.current + 1).max(1) - 1;
+        quickfix::jump_to_entry(quickfix, entry, buffers, layout, message);
+    } else if command == "copen" {
+        quickfix::open_window(quickfix, buffers, layout);
+    } else if command == "cclose" {
+        quickfix::close_window(buffers, layout);
+    } else if command == "Explore" || command == "Ex" || command == "Lexplore" || command == "Lex" {
+        explorer::toggle_window(explorer, buffers, layout);
+    } else if command == "Create" {
+        if arg.is_empty() {
+            *message = "No file name".to_string();
+        } else if let Err(err) = explorer::create_path(arg) {
+            *message = format!("Failed to create {}: {}", arg, err);
+        }
+        explorer::refresh(explorer, buffers);
+    } else if command == "Rename" {
+        // the rest of the line is the new path, for the entry under the cursor in the explorer or else the current file
+        let window = layout.current();
+        let from = if buffers[window.buffer].file_name == explorer::EXPLORER_NAME {
+            explorer.entries.get(window.cursor_y).map(|entry| explorer.get_path(&entry.path))
+        } else if buffers[window.buffer].scratch {
+            None
+        } else {
+            Some(buffers[window.buffer].file_name.clone())
+        };
+        match from {
+            _ if arg.is_empty() => *message = "No file name".to_string(),
+            Some(from) => {
+                if let Err(err) = explorer::rename_path(&from, arg, buffers) {
+                    *message = format!("Failed to rename {}: {}", from, err);
+                }
+            }
+            None => *message = "Nothing to rename".to_string(),
+        }
+        explorer::refresh(explorer, buffers);
+    } else if command == "Delete" {
+        if arg.is_empty() {
+            *message = "No file name".to_string();
+        } else if let Err(err) = explorer::delete_path(arg) {
+            *message = format!("Failed to delete {}: {}", arg, err);
+        }
+        explorer::refresh(explorer, buffers);
+    } else if command == "tabn" || command == "tabnext" {
+        tabs.next();
+    } else if command == "tabp" || command == "tabprevious" {
+        tabs.prev();
+    } else {
+        *message = format!("Not an editor command: {}", command_string);
+    }
+}
+
+// a line number, ., $, '< or '>, each optionally followed by +N or -N offsets
+fn parse_address(text: &str, cursor_y: usize, len: usize, last_visual: LineRange) -> Result<(Option<usize>, &str), String> {
+    let digits = text.len() - text.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let (mut line, mut rest) = if digits > 0 {
+        let number: usize = text[..digits].parse().map_err(|_| format!("Invalid range: {}", text))?;
+        (Some(number.saturating_sub(1)), &text[digits..])
+    } else if let Some(rest) = text.strip_prefix('.') {
+        (Some(cursor_y), rest)
+    } else if let Some(rest) = text.strip_prefix('$') {
+        (Some(len - 1), rest)
+    } else if let Some(rest) = text.strip_prefix("'<") {
+        (Some(last_visual.0), rest)
+    } else if let Some(rest) = text.strip_prefix("'>") {
+        (Some(last_visual.1), rest)
+    } else if text.starts_with('\'') {
+        return Err(format!("Unknown mark: {}", text.chars().take(2).collect::<String>()));
+    } else {
+        (None, text)
+    };
+    while let Some(sign) = rest.chars().next().filter(|c| *c == '+' || *c == '-') {
+        let after = &rest[1..];
+        let digits = after.len() - after.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let offset = if digits == 0 { 1 } else { after[..digits].parse().map_err(|_| format!("Invalid range: {}", text))? };
+        let base = line.unwrap_or(cursor_y);
+        line = match sign {
+            '+' => Some(base + offset),
+            _ => Some(base.checked_sub(offset).ok_or(format!("Invalid range: {}", text))?),
+        };
+        rest = &after[digits..];
+    }
+    Ok((line, rest))
+}
+
+// first and last line, 0-based and inclusive
+type LineRange = (usize, usize);
+
+// the lines before a command, as in :%!sort or :'<,'>!column -t, and the rest of the command
+fn parse_range(text: &str, cursor_y: usize, len: usize, last_visual: LineRange) -> Result<(Option<LineRange>, &str), String> {
+    if let Some(rest) = text.strip_prefix('%') {
+        return Ok((Some((0, len - 1)), rest));
+    }
+    let (start, rest) = parse_address(text, cursor_y, len, last_visual)?;
+    let Some(start) = start else { return Ok((None, text)) };
+    if let Some(rest) = rest.strip_prefix(',') {
+        let (end, rest) = parse_address(rest, cursor_y, len, last_visual)?;
+        let end = end.unwrap_or(cursor_y);
+        return Ok((Some((start.min(end), start.max(end))), rest));
+    }
+    Ok((Some((start, start)), rest))
+}
+
+// replaces the lines with what the command prints for them, leaving them alone if it fails
+fn filter_lines(
+    start: usize,
+    end: usize,
+    shell_command: &str,
+    buffer: &mut buffer::Buffer,
+    window: &mut window::Window,
+    message: &mut String,
+    ) {
+    if end >= buffer.file_data.len() {
+        *message = format!("Invalid range: {},{}", start + 1, end + 1);
+        return;
+    }
+    let input: String = (start..=end).map(|line| format!("{}\n", buffer.file_data[line])).collect();
+    if let Some(output) = error::report(helper::run_shell(shell_command, &input), message) {
+        buffer.file_data.remove_lines(start..end + 1);
+        insert_lines(start, output.lines().map(|line| line.to_string()).collect(), buffer, window);
+        *message = format!("{} lines filtered", end - start + 1);
+    }
+}
+
+fn insert_lines(at: usize, lines: Vec<String>, buffer: &mut buffer::Buffer, window: &mut window::Window) {
+    let at = at.min(buffer.file_data.len());
+    for (i, line) in lines.into_iter().enumerate() {
+        buffer.file_data.insert(at + i, line);
+    }
+    if buffer.file_data.len() == 0 {
+        buffer.file_data.insert(0, "".to_string());
+    }
+    window.cursor_y = at.min(buffer.file_data.len() - 1);
+    window.cursor_x = 0;
+}
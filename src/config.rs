@@ -0,0 +1,306 @@
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use crate::keymap;
+use crate::options::{self, Value};
+
+pub const PROJECT_CONFIG_NAME: &str = ".editor.toml";
+
+// one key = value line and the [section] it is under, "" before the first one
+pub struct ConfigEntry {
+    pub section: String,
+    pub key: String,
+    pub value: Value,
+    pub line: usize,
+}
+
+pub fn get_config_dir() -> Option<PathBuf> {
+    match env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => Some(PathBuf::from(dir).join("editor")),
+        _ => env::var("HOME").ok().map(|home| PathBuf::from(home).join(".config/editor")),
+    }
+}
+
+pub fn get_config_path() -> Option<PathBuf> {
+    get_config_dir().map(|dir| dir.join("config.toml"))
+}
+
+// the nearest .editor.toml in the working directory or above it
+pub fn find_project_config() -> Option<PathBuf> {
+    let mut dir = env::current_dir().ok()?;
+    loop {
+        let path = dir.join(PROJECT_CONFIG_NAME);
+        if path.is_file() {
+            return Some(path);
+        }
+        if !dir.pop() {
+            return None;
+        }
+    }
+}
+
+fn parse_string(text: &str, quote: char) -> Result<(String, &str), String> {
+    let mut value = String::new();
+    let mut chars = text.char_indices().skip(1);
+    while let Some((i, chr)) = chars.next() {
+        if chr == quote {
+            return Ok((value, &text[i + 1..]));
+        }
+        if chr == '\\' && quote == '"' {
+            value.push(match chars.next() {
+                Some((_, 'n')) => '\n',
+                Some((_, 't')) => '\t',
+                Some((_, 'r')) => '\r',
+                Some((_, '"')) => '"',
+                Some((_, '\\')) => '\\',
+                Some((_, other)) => return Err(format!("Unknown escape \\{} in string", other)),
+                None => break,
+            });
+        } else {
+            value.push(chr);
+        }
+    }
+    Err("Unterminated string".to_string())
+}
+
+// a string, integer, true, false or array of strings, and the text after it; an error comes with the text
+// where it was found, so one inside an array over several lines can be put on its own line
+fn parse_value(text: &str) -> Result<(Value, &str), (String, &str)> {
+    let text = text.trim_start();
+    if text.starts_with('"') || text.starts_with('\'') {
+        let (value, rest) = parse_string(text, text.chars().next().unwrap_or('"')).map_err(|err| (err, text))?;
+        return Ok((Value::Str(value), rest));
+    }
+    if let Some(mut rest) = text.strip_prefix('[') {
+        let mut items = Vec::new();
+        loop {
+            rest = skip_comments(rest);
+            if let Some(after) = rest.strip_prefix(']') {
+                return Ok((Value::List(items), after));
+            }
+            let (item, after) = parse_value(rest)?;
+            match item {
+                Value::Str(item) => items.push(item),
+                _ => return Err(("Arrays can only hold strings".to_string(), rest)),
+            }
+            let after = skip_comments(after);
+            rest = match after.strip_prefix(',') {
+                Some(after) => after,
+                None if after.starts_with(']') => after,
+                None => return Err(("Expected , or ] in array".to_string(), after)),
+            };
+        }
+    }
+    let end = text.find(|c: char| c.is_whitespace() || c == '#' || c == ',' || c == ']').unwrap_or(text.len());
+    let (word, rest) = text.split_at(end);
+    match word {
+        "true" => Ok((Value::Bool(true), rest)),
+        "false" => Ok((Value::Bool(false), rest)),
+        _ => match word.replace('_', "").parse() {
+            Ok(number) => Ok((Value::Int(number), rest)),
+            Err(_) => Err((format!("Expected a string, number, true, false or array, found {}", word), text)),
+        },
+    }
+}
+
+// whitespace, newlines and comments between array items
+fn skip_comments(mut text: &str) -> &str {
+    loop {
+        text = text.trim_start();
+        if !text.starts_with('#') {
+            return text;
+        }
+        text = text.split_once('\n').map(|(_, rest)| rest).unwrap_or("");
+    }
+}
+
+// how many [ are still open at the end of the text, leaving out the ones in strings and comments
+fn get_open_brackets(text: &str) -> usize {
+    let mut depth: usize = 0;
+    // the quote or # that the text is inside of
+    let mut inside = None;
+    let mut chars = text.chars();
+    while let Some(chr) = chars.next() {
+        match (inside, chr) {
+            // a string or comment does not go past the end of its line
+            (_, '\n') => inside = None,
+            (Some('#'), _) => (),
+            (Some('"'), '\\') => {
+                chars.next();
+            }
+            (Some(open), _) if chr == open => inside = None,
+            (Some(_), _) => (),
+            (None, '"' | '\'' | '#') => inside = Some(chr),
+            (None, '[') => depth += 1,
+            (None, ']') => depth = depth.saturating_sub(1),
+            (None, _) => (),
+        }
+    }
+    depth
+}
+
+fn parse_key(text: &str) -> Result<(String, &str), String> {
+    if text.starts_with('"') || text.starts_with('\'') {
+        return parse_string(text, text.chars().next().unwrap_or('"'));
+    }
+    let end = text.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-')).unwrap_or(text.len());
+    if end == 0 {
+        return Err(format!("Expected a key, found {}", text));
+    }
+    Ok((text[..end].to_string(), &text[end..]))
+}
+
+// the part of TOML a config needs: [sections], key = value, # comments and arrays over several lines
+pub fn parse_config(text: &str) -> (Vec<ConfigEntry>, Vec<(usize, String)>) {
+    let mut entries = Vec::new();
+    let mut errors = Vec::new();
+    let mut section = "".to_string();
+    let mut lines = text.lines().enumerate();
+    while let Some((index, line)) = lines.next() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(name) = line.strip_prefix('[') {
+            match name.split_once(']') {
+                Some((name, rest)) if !name.starts_with('[') && (rest.trim().is_empty() || rest.trim().starts_with('#')) => {
+                    section = name.trim().to_string();
+                }
+                _ => errors.push((line_number, format!("Invalid section header {}", line))),
+            }
+            continue;
+        }
+        let (key, rest) = match parse_key(line) {
+            Ok(parsed) => parsed,
+            Err(err) => {
+                errors.push((line_number, err));
+                continue;
+            }
+        };
+        let Some(value_text) = rest.trim_start().strip_prefix('=') else {
+            errors.push((line_number, format!("Expected = after {}", key)));
+            continue;
+        };
+        // an array goes on over the next lines until its brackets are closed
+        let mut value_text = value_text.to_string();
+        while get_open_brackets(&value_text) > 0 {
+            match lines.next() {
+                Some((_, next)) => value_text = format!("{}\n{}", value_text, next),
+                None => break,
+            }
+        }
+        match parse_value(&value_text) {
+            Ok((value, rest)) if rest.trim().is_empty() || rest.trim_start().starts_with('#') => {
+                entries.push(ConfigEntry { section: section.clone(), key, value, line: line_number });
+            }
+            Ok(_) => errors.push((line_number, format!("Unexpected text after the value of {}", key))),
+            Err((err, at)) => {
+                let offset = value_text.len() - at.len();
+                errors.push((line_number + value_text[..offset].matches('\n').count(), err));
+            }
+        }
+    }
+    (entries, errors)
+}
+
+// the user config and then the project config on top of it; returns what was wrong with them
+pub fn load_config(options: &mut options::Options, keymap: &mut keymap::Keymap, clean: bool) -> Vec<String> {
+    let mut errors = Vec::new();
+    if clean {
+        return errors;
+    }
+    for path in [get_config_path(), find_project_config()].into_iter().flatten() {
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+            Err(err) => {
+                errors.push(format!("{}: {}", path.display(), err));
+                continue;
+            }
+        };
+        let (mut entries, parse_errors) = parse_config(&text);
+        for (line, err) in parse_errors {
+            errors.push(format!("{}:{}: {}", path.display(), line, err));
+        }
+        // options first, so the leader is known whichever order the sections are in
+        entries.sort_by_key(|entry| !matches!(entry.section.as_str(), "" | "options"));
+        let mut unknown_sections = HashSet::new();
+        for entry in entries {
+            let map_command = keymap::MAP_COMMANDS.iter().find(|(name, _, _)| *name == entry.section);
+            let result = match (entry.section.as_str(), map_command, entry.value) {
+                ("" | "options", _, value) => options.set(&entry.key, value),
+                (_, Some(&(_, modes, recursive)), Value::Str(rhs)) if !rhs.is_empty() => {
+                    let arg = format!("{} {}", entry.key, rhs);
+                    keymap::run_map_command(keymap, modes, recursive, &arg, &options.leader).map(|_| ())
+                }
+                (section, Some(_), _) => Err(format!("[{}] mappings need a string of keys", section)),
+                (section, None, _) if unknown_sections.insert(section.to_string()) => Err(format!("Unknown section [{}]", section)),
+                _ => Ok(()),
+            };
+            if let Err(err) = result {
+                errors.push(format!("{}:{}: {}", path.display(), entry.line, err));
+            }
+        }
+    }
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn get_keys(entries: &[ConfigEntry]) -> Vec<(&str, &str)> {
+        entries.iter().map(|entry| (entry.section.as_str(), entry.key.as_str())).collect()
+    }
+
+    #[test]
+    fn parses_values() {
+        assert_eq!(parse_value(" true # on").unwrap(), (Value::Bool(true), " # on"));
+        assert_eq!(parse_value("1_000").unwrap(), (Value::Int(1000), ""));
+        assert_eq!(parse_value(r#""a\"b" x"#).unwrap(), (Value::Str("a\"b".to_string()), " x"));
+        assert_eq!(parse_value("'c:\\dir'").unwrap(), (Value::Str("c:\\dir".to_string()), ""));
+        assert_eq!(
+            parse_value("[\"a\", # one\n 'b',\n]").unwrap(),
+            (Value::List(vec!["a".to_string(), "b".to_string()]), "")
+        );
+        assert_eq!(parse_value("[1]").unwrap_err().0, "Arrays can only hold strings");
+        assert_eq!(parse_value("\"open").unwrap_err().0, "Unterminated string");
+        assert!(parse_value("maybe").is_err());
+    }
+
+    #[test]
+    fn parses_sections_and_arrays_over_lines() {
+        let (entries, errors) = parse_config("number = true\n[nmap]\nx = \"dd\"\n[rust]\nkeywords = [\n  \"fn\", # ]\n  \"let\",\n]\n");
+        assert!(errors.is_empty());
+        assert_eq!(get_keys(&entries), [("", "number"), ("nmap", "x"), ("rust", "keywords")]);
+        assert_eq!(entries[2].value, Value::List(vec!["fn".to_string(), "let".to_string()]));
+        assert_eq!(entries[2].line, 5);
+    }
+
+    #[test]
+    fn goes_on_after_a_bad_value() {
+        let (entries, errors) = parse_config("foo = [1, 2x]\nnumber = false\ntheme = \"light\"\n[nmap]\nx = \"dd\"\n");
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, 1);
+        assert_eq!(get_keys(&entries), [("", "number"), ("", "theme"), ("nmap", "x")]);
+    }
+
+    #[test]
+    fn reports_errors_on_their_line() {
+        let (entries, errors) = parse_config("list = [\n  \"a\",\n  b,\n]\n= 1\nnumber 1\n[bad\nend = [\n");
+        let lines: Vec<usize> = errors.iter().map(|(line, _)| *line).collect();
+        assert_eq!(lines, [3, 5, 6, 7, 8]);
+        assert!(entries.is_empty());
+    }
+
+    #[test]
+    fn counts_open_brackets() {
+        assert_eq!(get_open_brackets("[\"a\""), 1);
+        assert_eq!(get_open_brackets("[\"]\", '[' # ["), 1);
+        assert_eq!(get_open_brackets("[[]]"), 0);
+        assert_eq!(get_open_brackets("]"), 0);
+    }
+}
@@ -1,46 +1,234 @@
+use crate::text::{LineRope, TextBuffer};
+
+// snapshots share every unchanged leaf of the rope, so each one costs about one leaf per edit
 pub struct DiffHistory {
-    undo_stack: Vec<(Vec<String>, (usize, usize))>,
-    redo_stack: Vec<(Vec<String>, (usize, usize))>,
-    current_doc: Vec<String>,
-    current_pos: (usize, usize),
+    undo_stack: Vec<LineRope>,
+    redo_stack: Vec<LineRope>,
+    current_doc: LineRope,
 }
 
 
 impl DiffHistory {
-    pub fn new(doc: Vec<String>) -> Self {
+    pub fn new(doc: LineRope) -> Self {
         DiffHistory {
             current_doc: doc,
             undo_stack: Vec::new(),
             redo_stack: Vec::new(),
-            current_pos: (0, 0),
         }
     }
 
-    pub fn make_change(&mut self, new_doc: Vec<String>, new_pos: (usize, usize)) {
-        self.undo_stack.push((self.current_doc.clone(), self.current_pos));
+    pub fn make_change(&mut self, new_doc: &LineRope) -> bool {
+        if self.current_doc == *new_doc {
+            return false;
+        }
+        let old_doc = std::mem::replace(&mut self.current_doc, new_doc.clone());
+        self.undo_stack.push(old_doc);
         self.redo_stack.clear();
-        self.current_doc = new_doc;
-        self.current_pos = new_pos;
+        true
     }
 
-    pub fn undo(&mut self) -> Option<(Vec<String>, (usize, usize))> {
-        if let Some((old_doc, old_pos)) = self.undo_stack.pop() {
-            self.redo_stack.push((self.current_doc.clone(), self.current_pos));
-            self.current_doc = old_doc;
-            let pos = self.current_pos;
-            self.current_pos = old_pos;
+    pub fn undo(&mut self) -> Option<(LineRope, (usize, usize))> {
+        if let Some(old_doc) = self.undo_stack.pop() {
+            let pos = get_change_start(&self.current_doc, &old_doc);
+            let new_doc = std::mem::replace(&mut self.current_doc, old_doc);
+            self.redo_stack.push(new_doc);
             return Some((self.current_doc.clone(), pos));
         }
         None
     }
 
-    pub fn redo(&mut self) -> Option<(Vec<String>, (usize, usize))> {
-        if let Some((old_doc, old_pos)) = self.redo_stack.pop() {
-            self.undo_stack.push((self.current_doc.clone(), self.current_pos));
-            self.current_doc = old_doc;
-            self.current_pos = old_pos;
-            return Some((self.current_doc.clone(), self.current_pos));
+    pub fn redo(&mut self) -> Option<(LineRope, (usize, usize))> {
+        if let Some(new_doc) = self.redo_stack.pop() {
+            let pos = get_change_start(&self.current_doc, &new_doc);
+            let old_doc = std::mem::replace(&mut self.current_doc, new_doc);
+            self.undo_stack.push(old_doc);
+            return Some((self.current_doc.clone(), pos));
         }
         None
     }
-}
\ No newline at end of file
+}
+
+// position in `to` where it first differs from `from`
+pub fn get_change_start(from: &LineRope, to: &LineRope) -> (usize, usize) {
+    let y = from.first_difference(to).unwrap_or(from.len());
+    if y >= to.len() {
+        return (0, to.len().saturating_sub(1));
+    }
+    if y >= from.len() || from.len() != to.len() {
+        let x = to[y].len() - to[y].trim_start().len();
+        return (x, y);
+    }
+    let x = from[y].bytes().zip(to[y].bytes()).take_while(|(a, b)| a == b).count();
+    (x.min(to[y].len().saturating_sub(1)), y)
+}
+
+// index pairs of the lines `from` and `to` have in common, in order
+pub fn get_common_lines(from: &[String], to: &[String]) -> Vec<(usize, usize)> {
+    let prefix = from.iter().zip(to.iter()).take_while(|(a, b)| a == b).count();
+    let suffix = from[prefix..]
+        .iter()
+        .rev()
+        .zip(to[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let from_middle = &from[prefix..from.len() - suffix];
+    let to_middle = &to[prefix..to.len() - suffix];
+    let mut pairs: Vec<(usize, usize)> = (0..prefix).map(|i| (i, i)).collect();
+    // longest common subsequence of the changed middle, skipped when it would be too slow
+    if !from_middle.is_empty() && !to_middle.is_empty() && from_middle.len() * to_middle.len() <= 4_000_000 {
+        let width = to_middle.len() + 1;
+        let mut lengths = vec![0u32; (from_middle.len() + 1) * width];
+        for i in (0..from_middle.len()).rev() {
+            for j in (0..to_middle.len()).rev() {
+                lengths[i * width + j] = if from_middle[i] == to_middle[j] {
+                    lengths[(i + 1) * width + j + 1] + 1
+                } else {
+                    lengths[(i + 1) * width + j].max(lengths[i * width + j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < from_middle.len() && j < to_middle.len() {
+            if from_middle[i] == to_middle[j] {
+                pairs.push((prefix + i, prefix + j));
+                i += 1;
+                j += 1;
+            } else if lengths[(i + 1) * width + j] >= lengths[i * width + j + 1] {
+                i += 1;
+            } else {
+                j += 1;
+            }
+        }
+    }
+    for k in 0..suffix {
+        pairs.push((from.len() - suffix + k, to.len() - suffix + k));
+    }
+    pairs
+}
+
+// every line of both documents prefixed with ' ', '-' or '+'
+pub fn diff_lines(from: &[String], to: &[String]) -> Vec<String> {
+    let mut lines = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let end = (from.len(), to.len());
+    for (from_index, to_index) in get_common_lines(from, to).into_iter().chain(std::iter::once(end)) {
+        while i < from_index {
+            lines.push(format!("-{}", from[i]));
+            i += 1;
+        }
+        while j < to_index {
+            lines.push(format!("+{}", to[j]));
+            j += 1;
+        }
+        if i < from.len() && j < to.len() {
+            lines.push(format!(" {}", from[i]));
+        }
+        i += 1;
+        j += 1;
+    }
+    lines
+}
+
+// three-way merge of two edits of base, with conflict markers where both changed the same lines
+pub fn merge(base: &[String], ours: &[String], theirs: &[String]) -> (Vec<String>, usize) {
+    let ours_pairs: std::collections::HashMap<usize, usize> = get_common_lines(base, ours).into_iter().collect();
+    let theirs_pairs: std::collections::HashMap<usize, usize> = get_common_lines(base, theirs).into_iter().collect();
+    // lines neither side touched split the documents into chunks that merge independently
+    let mut anchors: Vec<(usize, usize, usize)> = (0..base.len())
+        .filter_map(|i| Some((i, *ours_pairs.get(&i)?, *theirs_pairs.get(&i)?)))
+        .collect();
+    anchors.push((base.len(), ours.len(), theirs.len()));
+    let mut merged = Vec::new();
+    let mut conflicts = 0;
+    let (mut base_start, mut ours_start, mut theirs_start) = (0, 0, 0);
+    for (base_end, ours_end, theirs_end) in anchors {
+        let base_chunk = &base[base_start..base_end];
+        let ours_chunk = &ours[ours_start..ours_end];
+        let theirs_chunk = &theirs[theirs_start..theirs_end];
+        if ours_chunk == base_chunk || ours_chunk == theirs_chunk {
+            merged.extend_from_slice(theirs_chunk);
+        } else if theirs_chunk == base_chunk {
+            merged.extend_from_slice(ours_chunk);
+        } else {
+            conflicts += 1;
+            merged.push("<<<<<<< ours".to_string());
+            merged.extend_from_slice(ours_chunk);
+            merged.push("=======".to_string());
+            merged.extend_from_slice(theirs_chunk);
+            merged.push(">>>>>>> theirs".to_string());
+        }
+        if base_end < base.len() {
+            merged.push(base[base_end].clone());
+        }
+        (base_start, ours_start, theirs_start) = (base_end + 1, ours_end + 1, theirs_end + 1);
+    }
+    (merged, conflicts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.split(' ').filter(|line| !line.is_empty()).map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn merges_edits_to_different_lines() {
+        let base = lines("a b c d e");
+        let ours = lines("A b c d e");
+        let theirs = lines("a b c d E f");
+        assert_eq!(merge(&base, &ours, &theirs), (lines("A b c d E f"), 0));
+        assert_eq!(merge(&base, &theirs, &ours), (lines("A b c d E f"), 0));
+    }
+
+    #[test]
+    fn merges_deletions_and_the_same_edit() {
+        let base = lines("a b c d e");
+        let ours = lines("a c d X");
+        let theirs = lines("a b c d X");
+        assert_eq!(merge(&base, &ours, &theirs), (lines("a c d X"), 0));
+        assert_eq!(merge(&base, &base, &base), (base.clone(), 0));
+        assert_eq!(merge(&base, &base, &theirs), (theirs.clone(), 0));
+    }
+
+    #[test]
+    fn marks_conflicts() {
+        let base = lines("a b c");
+        let ours = lines("a x c");
+        let theirs = lines("a y c");
+        let (merged, conflicts) = merge(&base, &ours, &theirs);
+        assert_eq!(conflicts, 1);
+        assert_eq!(merged, vec!["a", "<<<<<<< ours", "x", "=======", "y", ">>>>>>> theirs", "c"]);
+        let (_, conflicts) = merge(&lines("a"), &lines("x"), &lines("y z"));
+        assert_eq!(conflicts, 1);
+        let (merged, conflicts) = merge(&[], &lines("x"), &lines("y"));
+        assert_eq!(conflicts, 1);
+        assert_eq!(merged.len(), 5);
+    }
+
+    #[test]
+    fn finds_common_lines() {
+        let from = lines("a b c d e");
+        let to = lines("a c x d e");
+        assert_eq!(get_common_lines(&from, &to), vec![(0, 0), (2, 1), (3, 3), (4, 4)]);
+        assert_eq!(get_common_lines(&from, &[]), vec![]);
+        assert_eq!(diff_lines(&from, &to), vec![" a", "-b", " c", "+x", " d", " e"]);
+    }
+
+    #[test]
+    fn undoes_and_redoes_to_the_change() {
+        let mut history = DiffHistory::new(LineRope::from_lines(lines("a b c")));
+        let changed = LineRope::from_lines(vec!["a".to_string(), "bx".to_string(), "c".to_string()]);
+        assert!(!history.make_change(&LineRope::from_lines(lines("a b c"))));
+        assert!(history.make_change(&changed));
+        let (doc, pos) = history.undo().unwrap();
+        assert!(doc == lines("a b c"));
+        assert_eq!(pos, (0, 1));
+        assert!(history.undo().is_none());
+        let (doc, pos) = history.redo().unwrap();
+        assert!(doc == changed.to_vec());
+        assert_eq!(pos, (1, 1));
+        assert!(history.redo().is_none());
+    }
+}
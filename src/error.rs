@@ -0,0 +1,53 @@
+use std::fmt;
+use std::io;
+
+pub enum EditorError {
+    Io(String, io::Error),
+    Clipboard(String),
+    Conflict(String),
+    FileExists(String),
+    NoFileName,
+    ReadOnly(String),
+    Shell(String, String),
+    Unsaved(String),
+}
+
+impl fmt::Display for EditorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EditorError::Io(context, err) => write!(f, "{}: {}", context, err),
+            EditorError::Clipboard(err) => write!(f, "Clipboard error: {}", err),
+            EditorError::Conflict(file_name) => {
+                write!(f, "{} changed on disk: :reload to take it, :keep to keep ours, :merge to merge both", file_name)
+            }
+            EditorError::FileExists(file_name) => write!(f, "{} exists, :w! to overwrite it", file_name),
+            EditorError::NoFileName => write!(f, "No file name"),
+            EditorError::Shell(command, reason) => write!(f, "{} failed: {}", command, reason),
+            EditorError::ReadOnly(file_name) => write!(f, "{} is read only, :set noreadonly to allow changes", file_name),
+            EditorError::Unsaved(file_name) => write!(f, "{} has unsaved changes, :q! to quit without saving", file_name),
+        }
+    }
+}
+
+pub type Result<T> = std::result::Result<T, EditorError>;
+
+// shows the error in the message area instead of stopping the editor
+pub fn report<T>(result: Result<T>, message: &mut String) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(err) => {
+            *message = err.to_string();
+            None
+        }
+    }
+}
+
+// single line messages are kept for :messages, listings like :ls are not
+pub fn add_to_history(message: &str, history: &mut Vec<String>) {
+    if !message.is_empty() && !message.contains('\n') {
+        history.push(message.to_string());
+        if history.len() > 200 {
+            history.remove(0);
+        }
+    }
+}
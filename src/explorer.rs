@@ -0,0 +1,307 @@
+use crossterm::event::KeyCode;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io;
+use std::path::Path;
+use std::process::Command;
+use crate::buffer;
+use crate::command;
+use crate::window;
+
+pub const EXPLORER_NAME: &str = "[Explorer]";
+pub const EXPLORER_WIDTH: usize = 30;
+
+pub struct ExplorerEntry {
+    pub path: String,
+    pub depth: usize,
+    pub is_dir: bool,
+}
+
+pub struct Explorer {
+    pub root: String,
+    pub expanded: HashSet<String>,
+    pub entries: Vec<ExplorerEntry>,
+    pub git_status: HashMap<String, char>,
+}
+
+impl Explorer {
+    pub fn new(root: &str) -> Self {
+        Explorer {
+            root: root.to_string(),
+            expanded: HashSet::new(),
+            entries: Vec::new(),
+            git_status: HashMap::new(),
+        }
+    }
+
+    pub fn refresh(&mut self) {
+        self.entries = Vec::new();
+        let root = self.root.clone();
+        self.add_entries(&root, "", 0);
+        self.git_status = get_git_status(&self.root);
+    }
+
+    fn add_entries(&mut self, dir: &str, prefix: &str, depth: usize) {
+        for (name, is_dir) in read_dir_sorted(Path::new(dir)) {
+            let path = format!("{}{}", prefix, name);
+            self.entries.push(ExplorerEntry { path: path.clone(), depth, is_dir });
+            if is_dir && self.expanded.contains(&path) {
+                self.add_entries(&format!("{}/{}", dir, name), &format!("{}/", path), depth + 1);
+            }
+        }
+    }
+
+    // a directory gets the marker of the first changed file inside it
+    fn get_marker(&self, entry: &ExplorerEntry) -> char {
+        if let Some(marker) = self.git_status.get(&entry.path) {
+            return *marker;
+        }
+        if entry.is_dir {
+            let prefix = format!("{}/", entry.path);
+            if let Some((_, marker)) = self.git_status.iter().find(|(path, _)| path.starts_with(&prefix)) {
+                return *marker;
+            }
+        }
+        ' '
+    }
+
+    pub fn get_lines(&self) -> Vec<String> {
+        self.entries
+            .iter()
+            .map(|entry| {
+                let name = entry.path.rsplit('/').next().unwrap_or(&entry.path);
+                let icon = if !entry.is_dir {
+                    ' '
+                } else if self.expanded.contains(&entry.path) {
+                    '-'
+                } else {
+                    '+'
+                };
+                format!(
+                    "{} {}{} {}{}",
+                    self.get_marker(entry),
+                    "  ".repeat(entry.depth),
+                    icon,
+                    name,
+                    if entry.is_dir { "/" } else { "" },
+                )
+            })
+            .collect()
+    }
+
+    // the directory new files go in when the cursor is on entry
+    pub fn get_dir(&self, entry: usize) -> String {
+        match self.entries.get(entry) {
+            Some(entry) if entry.is_dir => self.get_path(&format!("{}/", entry.path)),
+            Some(entry) => match entry.path.rfind('/') {
+                Some(end) => self.get_path(&entry.path[..end + 1]),
+                None => self.get_path(""),
+            },
+            None => self.get_path(""),
+        }
+    }
+
+    // entry paths are relative to the root, this makes them relative to the working directory
+    pub fn get_path(&self, path: &str) -> String {
+        if self.root == "." {
+            path.to_string()
+        } else {
+            format!("{}/{}", self.root.trim_end_matches('/'), path)
+        }
+    }
+}
+
+// directories first, then files, each sorted by name; .git is hidden
+fn read_dir_sorted(dir: &Path) -> Vec<(String, bool)> {
+    let mut entries: Vec<(String, bool)> = match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .flatten()
+            .map(|entry| (entry.file_name().to_string_lossy().to_string(), entry.path().is_dir()))
+            .filter(|(name, _)| name != ".git")
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    entries
+}
+
+// `git status --short` paths relative to root, marked M, A, D, R or ? for untracked
+pub fn get_git_status(root: &str) -> HashMap<String, char> {
+    let mut status = HashMap::new();
+    let output = Command::new("git")
+        .args(["-c", "status.relativePaths=true", "status", "--short", "--untracked-files=all"])
+        .current_dir(root)
+        .output();
+    let output = match output {
+        Ok(output) if output.status.success() => output,
+        _ => return status,
+    };
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        if line.len() < 4 {
+            continue;
+        }
+        let code = &line[..2];
+        let path = line[3..].rsplit(" -> ").next().unwrap_or("");
+        let path = path.trim_matches('"').trim_end_matches('/');
+        let marker = if code == "??" {
+            '?'
+        } else {
+            code.chars().find(|c| *c != ' ').unwrap_or(' ')
+        };
+        status.insert(path.to_string(), marker);
+    }
+    status
+}
+
+// rebuilds the tree and the explorer buffer if it is open
+pub fn refresh(explorer: &mut Explorer, buffers: &mut [buffer::Buffer]) {
+    if let Some(index) = buffer::find_buffer(buffers, EXPLORER_NAME) {
+        explorer.refresh();
+        buffers[index].set_data(explorer.get_lines());
+    }
+}
+
+// lists a directory in the window, for :e dir and directory arguments
+pub fn open_dir(dir: &str, explorer: &mut Explorer, buffers: &mut Vec<buffer::Buffer>, window: &mut window::Window) {
+    explorer.root = dir.to_string();
+    explorer.expanded.clear();
+    let index = match buffer::find_buffer(buffers, EXPLORER_NAME) {
+        Some(index) => index,
+        None => {
+            buffers.push(buffer::Buffer::scratch(EXPLORER_NAME, Vec::new()));
+            buffers.len() - 1
+        }
+    };
+    refresh(explorer, buffers);
+    window.set_buffer(buffers, index);
+}
+
+// opens the explorer on the left, or closes it if it is already showing
+pub fn toggle_window(explorer: &mut Explorer, buffers: &mut Vec<buffer::Buffer>, layout: &mut window::Layout) {
+    let index = match buffer::find_buffer(buffers, EXPLORER_NAME) {
+        Some(index) => index,
+        None => {
+            buffers.push(buffer::Buffer::scratch(EXPLORER_NAME, Vec::new()));
+            buffers.len() - 1
+        }
+    };
+    if let Some(window) = layout.find_window(index) {
+        if window == layout.current_window {
+            layout.close_window(window);
+        } else {
+            layout.current_window = window;
+        }
+        return;
+    }
+    refresh(explorer, buffers);
+    layout.split_left(window::Window::new(buffers, index), EXPLORER_WIDTH);
+}
+
+// moves to the last used window showing a file, splitting if there is none
+fn open_file(file_name: &str, buffers: &mut Vec<buffer::Buffer>, layout: &mut window::Layout, message: &mut String) {
+    match layout.windows.iter().position(|window| !buffers[window.buffer].scratch) {
+        Some(window) => layout.current_window = window,
+        None => layout.split(true),
+    }
+    command::edit_file(file_name, buffers, layout.current(), message);
+}
+
+// keys in the explorer window; returns false for keys that should act as usual
+pub fn send_explorer_command(
+    code: KeyCode,
+    explorer: &mut Explorer,
+    buffers: &mut Vec<buffer::Buffer>,
+    layout: &mut window::Layout,
+    mode: &mut char,
+    command_string: &mut String,
+    message: &mut String,
+    ) -> bool {
+    let cursor_y = layout.current().cursor_y;
+    let (path, is_dir) = match explorer.entries.get(cursor_y) {
+        Some(entry) => (entry.path.clone(), entry.is_dir),
+        None => ("".to_string(), false),
+    };
+    match code {
+        KeyCode::Enter | KeyCode::Char('l') | KeyCode::Char('o') => {
+            if path.is_empty() {
+                return true;
+            }
+            if is_dir {
+                if !explorer.expanded.remove(&path) {
+                    explorer.expanded.insert(path);
+                }
+                refresh(explorer, buffers);
+            } else {
+                open_file(&explorer.get_path(&path), buffers, layout, message);
+            }
+        }
+        KeyCode::Char('h') => {
+            // collapse the directory under the cursor, or the one containing it
+            let dir = if is_dir && explorer.expanded.contains(&path) {
+                path
+            } else {
+                match path.rsplit_once('/') {
+                    Some((parent, _)) => parent.to_string(),
+                    None => return true,
+                }
+            };
+            if explorer.expanded.remove(&dir) {
+                refresh(explorer, buffers);
+                if let Some(entry) = explorer.entries.iter().position(|entry| entry.path == dir) {
+                    layout.current().cursor_y = entry;
+                }
+            }
+        }
+        KeyCode::Char('R') => refresh(explorer, buffers),
+        KeyCode::Char('a') => {
+            *mode = ':';
+            *command_string = format!("Create {}", explorer.get_dir(cursor_y));
+        }
+        KeyCode::Char('r') if !path.is_empty() => {
+            *mode = ':';
+            *command_string = format!("Rename {}", explorer.get_path(&path));
+        }
+        KeyCode::Char('d') if !path.is_empty() => {
+            *mode = ':';
+            *command_string = format!("Delete {}", explorer.get_path(&path));
+        }
+        _ => return false,
+    }
+    true
+}
+
+// a trailing slash creates a directory
+pub fn create_path(path: &str) -> io::Result<()> {
+    if path.ends_with('/') {
+        return fs::create_dir_all(path);
+    }
+    if let Some(parent) = Path::new(path).parent() {
+        if !parent.as_os_str().is_empty() {
+            fs::create_dir_all(parent)?;
+        }
+    }
+    OpenOptions::new().write(true).create_new(true).open(path)?;
+    Ok(())
+}
+
+// renames the file on disk and any buffer that has it open
+pub fn rename_path(from: &str, to: &str, buffers: &mut [buffer::Buffer]) -> io::Result<()> {
+    fs::rename(from, to)?;
+    let prefix = format!("{}/", from);
+    for buffer in buffers.iter_mut() {
+        if buffer.file_name == from {
+            buffer.file_name = to.to_string();
+        } else if let Some(rest) = buffer.file_name.strip_prefix(&prefix) {
+            buffer.file_name = format!("{}/{}", to, rest);
+        }
+    }
+    Ok(())
+}
+
+pub fn delete_path(path: &str) -> io::Result<()> {
+    if Path::new(path).is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
@@ -0,0 +1,301 @@
+use crossterm::event::{KeyCode, KeyModifiers};
+use crossterm::style::Color;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::process::Command;
+use crate::buffer;
+use crate::command;
+use crate::tab;
+use crate::theme::Theme;
+use crate::window;
+
+pub struct Finder {
+    pub query: String,
+    pub files: Vec<String>,
+    pub matches: Vec<String>,
+    pub selected: usize,
+}
+
+impl Finder {
+    pub fn new() -> Self {
+        Finder {
+            query: "".to_string(),
+            files: Vec::new(),
+            matches: Vec::new(),
+            selected: 0,
+        }
+    }
+
+    pub fn open(&mut self, root: &str) {
+        self.files = get_project_files(root);
+        self.query = "".to_string();
+        self.update_matches();
+    }
+
+    pub fn update_matches(&mut self) {
+        let mut scored: Vec<(i64, &String)> = self.files
+            .iter()
+            .filter_map(|file| fuzzy_score(&self.query, file).map(|score| (score, file)))
+            .collect();
+        scored.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.len().cmp(&b.1.len())).then(a.1.cmp(b.1)));
+        self.matches = scored.into_iter().map(|(_, file)| file.clone()).collect();
+        self.selected = 0;
+    }
+}
+
+// files tracked or untracked-but-not-ignored by git, falling back to walking the tree
+pub fn get_project_files(root: &str) -> Vec<String> {
+    let output = Command::new("git")
+        .args(["ls-files", "--cached", "--others", "--exclude-standard"])
+        .current_dir(root)
+        .output();
+    if let Ok(output) = output {
+        if output.status.success() {
+            let mut files: Vec<String> = String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .filter(|line| Path::new(root).join(line).is_file())
+                .map(|line| line.to_string())
+                .collect();
+            files.sort();
+            files.dedup();
+            return files;
+        }
+    }
+    let mut files = Vec::new();
+    walk_dir(Path::new(root), "", &mut files);
+    files.sort();
+    files
+}
+
+fn walk_dir(dir: &Path, prefix: &str, files: &mut Vec<String>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') || name == "target" || name == "node_modules" {
+            continue;
+        }
+        let path = format!("{}{}", prefix, name);
+        match entry.file_type() {
+            Ok(file_type) if file_type.is_dir() => walk_dir(&entry.path(), &format!("{}/", path), files),
+            Ok(file_type) if file_type.is_file() => files.push(path),
+            _ => (),
+        }
+    }
+}
+
+// subsequence match, rewarding consecutive characters and word starts; smart case
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<i64> {
+    if query.is_empty() {
+        return Some(0);
+    }
+    let case_sensitive = query.chars().any(|c| c.is_uppercase());
+    let normalize = |c: char| if case_sensitive { c } else { c.to_ascii_lowercase() };
+    let query: Vec<char> = query.chars().map(normalize).collect();
+    let candidate: Vec<char> = candidate.chars().collect();
+    let basename_start = candidate.iter().rposition(|&c| c == '/').map(|i| i + 1).unwrap_or(0);
+    let mut score = 0;
+    let mut query_index = 0;
+    let mut prev_match: Option<usize> = None;
+    for (i, &c) in candidate.iter().enumerate() {
+        if query_index == query.len() {
+            break;
+        }
+        if normalize(c) != query[query_index] {
+            continue;
+        }
+        score += 16;
+        if prev_match == Some(i.wrapping_sub(1)) {
+            score += 12;
+        } else if let Some(prev) = prev_match {
+            score -= (i - prev) as i64;
+        }
+        if i == 0 || matches!(candidate[i - 1], '/' | '_' | '-' | '.' | ' ') {
+            score += 10;
+        }
+        if i >= basename_start {
+            score += 4;
+        }
+        prev_match = Some(i);
+        query_index += 1;
+    }
+    if query_index == query.len() {
+        Some(score)
+    } else {
+        None
+    }
+}
+
+pub fn send_finder_command(
+    code: KeyCode,
+    modifiers: KeyModifiers,
+    finder: &mut Finder,
+    buffers: &mut Vec<buffer::Buffer>,
+    tabs: &mut tab::Tabs,
+    mode: &mut char,
+    message: &mut String,
+    ) {
+    let control = modifiers.contains(KeyModifiers::CONTROL);
+    if code == KeyCode::Esc {
+        *mode = 'n';
+    } else if code == KeyCode::Down || (control && (code == KeyCode::Char('n') || code == KeyCode::Char('j'))) {
+        if finder.selected + 1 < finder.matches.len() {
+            finder.selected += 1;
+        }
+    } else if code == KeyCode::Up || (control && (code == KeyCode::Char('p') || code == KeyCode::Char('k'))) {
+        finder.selected = finder.selected.saturating_sub(1);
+    } else if code == KeyCode::Enter || (control && matches!(code, KeyCode::Char('s') | KeyCode::Char('v') | KeyCode::Char('t'))) {
+        let file_name = match finder.matches.get(finder.selected) {
+            Some(file_name) => file_name.clone(),
+            None => return,
+        };
+        if code == KeyCode::Char('s') || code == KeyCode::Char('v') {
+            tabs.current().split(code == KeyCode::Char('v'));
+        } else if code == KeyCode::Char('t') {
+            tabs.new_tab();
+        }
+        command::edit_file(&file_name, buffers, tabs.current().current(), message);
+        *mode = 'n';
+    } else if code == KeyCode::Backspace {
+        finder.query.pop();
+        finder.update_matches();
+    } else if let KeyCode::Char(c) = code {
+        // control keys without a binding here are not part of the query
+        if !control {
+            finder.query.push(c);
+            finder.update_matches();
+        }
+    }
+}
+
+fn get_preview(file_name: &str, height: usize) -> Vec<String> {
+    match File::open(file_name) {
+        Ok(file) => BufReader::new(file).lines().take(height).map_while(Result::ok).collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+fn push_text(row: &mut Vec<(char, Color, Color, bool)>, text: &str, width: usize, fg: Color, bg: Color, reverse: bool) {
+    let mut chars = text.chars();
+    for _ in 0..width {
+        let chr = chars.next().unwrap_or(' ');
+        let chr = if chr.is_control() { ' ' } else { chr };
+        row.push((chr, fg, bg, reverse));
+    }
+}
+
+// a centered box with the prompt and matches on the left and a preview on the right, none if the terminal is too small for it
+pub fn get_overlay(finder: &Finder, term_width: usize, term_height: usize, theme: &Theme) -> Option<window::Overlay> {
+    let width = (term_width * 9 / 10).max(20).min(term_width);
+    let height = (term_height * 8 / 10).max(5).min(term_height);
+    if width < 5 || height < 3 {
+        return None;
+    }
+    let x = (term_width - width) / 2;
+    let y = (term_height - height) / 2;
+    let list_width = (width - 3) / 2;
+    let preview_width = width - 3 - list_width;
+    let inner_height = height - 2;
+    let border = |chr| (chr, theme.dim, theme.bg, false);
+    let preview = match finder.matches.get(finder.selected) {
+        Some(file_name) => get_preview(file_name, inner_height),
+        None => Vec::new(),
+    };
+    let first_match = (finder.selected + 2).saturating_sub(inner_height);
+    let mut rows = Vec::new();
+    let mut top = vec![border('┌')];
+    top.resize(list_width + 1, border('─'));
+    top.push(border('┬'));
+    top.resize(width - 1, border('─'));
+    top.push(border('┐'));
+    rows.push(top);
+    for i in 0..inner_height {
+        let mut row = vec![border('│')];
+        if i == 0 {
+            let prompt = format!("> {}", finder.query);
+            push_text(&mut row, &prompt, list_width, theme.fg, theme.bg, false);
+        } else if let Some(file_name) = finder.matches.get(first_match + i - 1) {
+            let is_selected = first_match + i - 1 == finder.selected;
+            push_text(&mut row, file_name, list_width, theme.fg, theme.bg, is_selected);
+        } else {
+            push_text(&mut row, "", list_width, theme.fg, theme.bg, false);
+        }
+        row.push(border('│'));
+        let line = preview.get(i).map(|line| line.as_str()).unwrap_or("");
+        push_text(&mut row, line, preview_width, theme.preview, theme.bg, false);
+        row.push(border('│'));
+        rows.push(row);
+    }
+    let mut bottom = vec![border('└')];
+    bottom.resize(list_width + 1, border('─'));
+    bottom.push(border('┴'));
+    bottom.resize(width - 1, border('─'));
+    bottom.push(border('┘'));
+    rows.push(bottom);
+    Some(window::Overlay {
+        x,
+        y,
+        rows,
+        cursor: ((3 + finder.query.chars().count()).min(list_width), 1),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_subsequences() {
+        assert_eq!(fuzzy_score("", "src/main.rs"), Some(0));
+        assert!(fuzzy_score("smr", "src/main.rs").is_some());
+        assert_eq!(fuzzy_score("rsm", "src/main.rs"), None);
+        assert_eq!(fuzzy_score("mainx", "src/main.rs"), None);
+    }
+
+    #[test]
+    fn uses_smart_case() {
+        assert!(fuzzy_score("main", "src/Main.rs").is_some());
+        assert!(fuzzy_score("Main", "src/Main.rs").is_some());
+        assert_eq!(fuzzy_score("Main", "src/main.rs"), None);
+    }
+
+    #[test]
+    fn prefers_consecutive_matches_and_word_starts() {
+        let consecutive = fuzzy_score("main", "src/main.rs").unwrap();
+        let scattered = fuzzy_score("main", "src/my_animation.rs").unwrap();
+        assert!(consecutive > scattered);
+        let word_start = fuzzy_score("b", "a_b").unwrap();
+        let inside = fuzzy_score("b", "ab").unwrap();
+        assert!(word_start > inside);
+    }
+
+    #[test]
+    fn prefers_the_basename() {
+        let basename = fuzzy_score("tab", "src/tab.rs").unwrap();
+        let directory = fuzzy_score("tab", "tab/src.rs").unwrap();
+        assert!(basename > directory);
+    }
+
+    #[test]
+    fn skips_the_overlay_when_the_terminal_is_too_small() {
+        let theme = crate::theme::get_theme("dark").unwrap();
+        let finder = Finder::new();
+        assert!(get_overlay(&finder, 4, 24, &theme).is_none());
+        assert!(get_overlay(&finder, 80, 2, &theme).is_none());
+        let overlay = get_overlay(&finder, 5, 3, &theme).unwrap();
+        assert_eq!(overlay.rows.len(), 3);
+        assert!(overlay.rows.iter().all(|row| row.len() == 5));
+    }
+
+    #[test]
+    fn sorts_matches_by_score_then_length() {
+        let mut finder = Finder::new();
+        finder.files = vec!["src/text.rs".to_string(), "tests/x.rs".to_string(), "src/tab/text.rs".to_string(), "README".to_string()];
+        finder.query = "text".to_string();
+        finder.update_matches();
+        assert_eq!(finder.matches, vec!["src/text.rs", "src/tab/text.rs"]);
+    }
+}
@@ -1,59 +1,70 @@
 use crossterm::execute;
 use crossterm::terminal::{disable_raw_mode, LeaveAlternateScreen};
-use crossterm::cursor::{SetCursorStyle, MoveTo, MoveToColumn, MoveToRow};
+use crossterm::cursor::MoveTo;
 use crossterm::style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor, SetAttribute, Attribute};
 use crossterm::event::{KeyCode, KeyModifiers};
-use std::fs::File;
-use std::io::{self, stdout, BufRead, BufReader, Write};
+use std::fs::{File, OpenOptions};
+use std::io::{self, stdout, BufRead, BufReader, IsTerminal, Write};
 use crossterm::terminal::size;
+use std::path::Path;
 use std::process::{Command, Stdio};
-use crate::diffhist;
-
-pub fn get_clipboard_content() -> String {
-    #[cfg(target_os = "macos")]
-    let output = Command::new("pbpaste")
-        .output()
-        .expect("Failed to execute command");
-    #[cfg(target_os = "linux")]
-    let output = Command::new("xsel")
-        .args(&["-o", "-b"]) // -o for output, -b for the clipboard
-        .output()
-        .expect("Failed to execute command");
-    String::from_utf8(output.stdout).expect("Invalid UTF-8 data")
-}
-
-pub fn copy_to_clipboard(s: &str) -> Option<()> {
-    #[cfg(target_os = "macos")]
-    let mut command = Command::new("pbcopy");
-    #[cfg(target_os = "linux")]
-    let mut command = {
-        // See https://linux.die.net/man/1/xclip.
-        let mut c = Command::new("xclip");
-        c.arg("-in");
-        c.arg("-selection");
-        c.arg("clipboard");
-        c
+use std::thread;
+use crate::error;
+use crate::syntax::{self, Highlights, Kind};
+use crate::tree::SyntaxTree;
+use crate::text::{LineRope, TextBuffer};
+use crate::theme::Theme;
+use crate::window::Window;
+
+// the program and arguments that copy to or paste from the clipboard of a provider
+fn get_clipboard_command(provider: &str, copy: bool) -> (&'static str, &'static [&'static str]) {
+    let provider = match provider {
+        "auto" if cfg!(target_os = "macos") => "macos",
+        "auto" if std::env::var_os("WAYLAND_DISPLAY").is_some() => "wayland",
+        // xclip copies and xsel pastes, as before there was a choice
+        "auto" if copy => "xclip",
+        "auto" => "xsel",
+        provider => provider,
     };
-    let mut child = command.stdin(Stdio::piped()).spawn().ok()?;
+    match (provider, copy) {
+        ("macos", true) => ("pbcopy", &[]),
+        ("macos", false) => ("pbpaste", &[]),
+        ("wayland", true) => ("wl-copy", &[]),
+        ("wayland", false) => ("wl-paste", &["-n"]),
+        ("xsel", true) => ("xsel", &["-i", "-b"]),
+        ("xsel", false) => ("xsel", &["-o", "-b"]),
+        ("tmux", true) => ("tmux", &["load-buffer", "-"]),
+        ("tmux", false) => ("tmux", &["save-buffer", "-"]),
+        (_, true) => ("xclip", &["-in", "-selection", "clipboard"]),
+        (_, false) => ("xclip", &["-out", "-selection", "clipboard"]),
+    }
+}
+
+pub fn get_clipboard_content(provider: &str) -> error::Result<String> {
+    let (program, args) = get_clipboard_command(provider, false);
+    let output = Command::new(program)
+        .args(args)
+        .output()
+        .map_err(|err| error::EditorError::Clipboard(format!("{}: {}", program, err)))?;
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+pub fn copy_to_clipboard(s: &str, provider: &str) -> error::Result<()> {
+    let (program, args) = get_clipboard_command(provider, true);
+    let clipboard_error = |err: io::Error| error::EditorError::Clipboard(format!("{}: {}", program, err));
+    let mut child = Command::new(program).args(args).stdin(Stdio::piped()).spawn().map_err(clipboard_error)?;
     // When stdin is dropped the fd is automatically closed. See
     // https://doc.rust-lang.org/std/process/struct.ChildStdin.html.
-    {
-        let stdin = child.stdin.as_mut()?;
-        stdin.write_all(s.as_bytes()).ok()?;
+    if let Some(stdin) = child.stdin.as_mut() {
+        stdin.write_all(s.as_bytes()).map_err(clipboard_error)?;
     }
-    // Wait on pbcopy/xclip to finish.
-    child.wait().ok()?;
-    Some(())
+    // Wait on the copy program to finish.
+    child.wait().map_err(clipboard_error)?;
+    Ok(())
 }
 
-pub fn center_screen(cursor_y: usize) -> (usize, usize) {
-    let (_, height) = size().expect("Failed to find terminal size");
-    let new_window_line_y = if cursor_y >= (height / 2).into() {
-        cursor_y - (height / 2) as usize
-    } else {
-        0
-    };
-    (0 as usize, new_window_line_y)
+pub fn center_screen(cursor_y: usize, height: usize) -> (usize, usize) {
+    (0, cursor_y.saturating_sub(height / 2))
 }
 
 pub fn get_file_data(file_name: &str) -> io::Result<Vec<String>> {
@@ -64,13 +75,28 @@ pub fn get_file_data(file_name: &str) -> io::Result<Vec<String>> {
     Ok(lines)
 }
 
+pub fn get_stdin_data() -> io::Result<Vec<String>> {
+    io::stdin().lock().lines().collect()
+}
+
+// "\r\n" if the first line of the file ends with it, otherwise "\n"
+pub fn get_line_ending(file_name: &str) -> io::Result<String> {
+    let mut first_line = Vec::new();
+    BufReader::new(File::open(file_name)?).read_until(b'\n', &mut first_line)?;
+    if first_line.ends_with(b"\r\n") {
+        Ok("\r\n".to_string())
+    } else {
+        Ok("\n".to_string())
+    }
+}
+
 pub fn update_terminal(
     prev_render: &Vec<Vec<(char, Color, Color, bool)>>,
     current_render: &Vec<Vec<(char, Color, Color, bool)>>,
     full_render: bool) {
 
-    let mut stdout = stdout();
-    let (width, height) = size().expect("Failed to find terminal size");
+    let mut stdout = get_terminal_output();
+    let (width, height) = size().unwrap_or((80, 24));
     let height = height as usize;
     let width = width as usize;
     for y in 0..height {
@@ -106,11 +132,11 @@ pub fn update_terminal(
 }
 
 pub fn render_file_data(
-    prev_view: Vec<Vec<(char, Color, Color, bool)>>,
-    file_name: &str,
-    file_data: &[String],
+    highlights: &Highlights,
+    tree: &SyntaxTree,
+    file_data: &LineRope,
+    lines: &[usize],
     window_line_x: usize,
-    window_line_y: usize,
     cursor_x: usize,
     cursor_y: usize,
     visual_x: usize,
@@ -119,172 +145,192 @@ pub fn render_file_data(
     search_string: String,
     searching: bool,
     macro_recording: bool,
-    full_render: bool,
+    signs: &[(usize, char)],
+    number: bool,
+    theme: &Theme,
+    width: usize,
 ) -> Vec<Vec<(char, Color, Color, bool)>> {
-    let mut stdout = stdout();
-    if mode == 'i' {
-        execute!(stdout, SetCursorStyle::SteadyBar).unwrap();
-    } else {
-        execute!(stdout, SetCursorStyle::DefaultUserShape).unwrap();
-    }
-    let terminal_size = size().unwrap();
-    let term_height = terminal_size.1 as usize;
-    let term_width = terminal_size.0 as usize;
+    let text_width = width.saturating_sub(get_gutter_width(number));
     let mut screen_view: Vec<Vec<(char, Color, Color, bool)>> = Vec::new();
-    let mut y = 0;
     let fg = if macro_recording {
-        Color::Red
+        theme.recording
     } else {
-        Color::DarkGrey
+        theme.dim
     };
-    while y < term_height && window_line_y + y < file_data.len() {
-        execute!(stdout, MoveToRow((y as u16).try_into().unwrap())).expect("Failed to move cursor");
-        let mut line: String = if file_data[window_line_y + y].len() >= window_line_x {
-            file_data[window_line_y + y][window_line_x..].to_string()
+    let kinds = tree.get_kinds(file_data, lines).unwrap_or_else(|| highlights.get_kinds(file_data, lines));
+    for (y, &line_y) in lines.iter().enumerate() {
+        let mut line: String = if file_data[line_y].len() >= window_line_x {
+            file_data[line_y][window_line_x..].to_string()
         } else {
             "".to_string()
         };
-        if line.len() > term_width {
-            let substring = &line[..term_width];
-            line = String::from(substring);
+        if line.len() > text_width {
+            line = line.chars().take(text_width).collect();
         }
-        let line_num_chars = format!("{:4} ", window_line_y + y + 1);
+        let line_num_chars = if number { format!("{:4} ", line_y + 1) } else { "".to_string() };
         let mut line_render = Vec::new();
         for num in line_num_chars.chars() {
-            line_render.push((num, fg, Color::Black, false));
+            line_render.push((num, fg, theme.bg, false));
+        }
+        // errors take precedence over warnings on the same line
+        let sign = signs
+            .iter()
+            .filter(|(line, _)| *line == line_y)
+            .map(|(_, sign)| *sign)
+            .min();
+        // without line numbers there is no gutter to show the sign in
+        if let Some(sign) = sign.filter(|_| number) {
+            let sign_color = if sign == 'E' { theme.error } else { theme.warning };
+            if line_render[0].0 == ' ' {
+                line_render[0] = (sign, sign_color, theme.bg, false);
+            } else {
+                for cell in line_render.iter_mut() {
+                    cell.1 = sign_color;
+                }
+            }
         }
-        let line_chars = line.chars();
-        let comment_string = match get_comment_string(file_name) {
-            Some(str) => str,
-            None => "#",
-        };
-        let comment_index = match find_substring(&line, comment_string) {
-            Some(number) => number,
-            None => usize::MAX,
+        // a closed fold shows as one line with how many it hides
+        if let Some((_, end)) = tree.get_closed_fold(line_y).filter(|(start, _)| *start == line_y) {
+            let summary = format!("+--{:>3} lines: {}", end - line_y + 1, file_data[line_y].trim());
+            let highlight = mode == 'V' && is_line_highlighted(line_y, visual_y, cursor_y);
+            for chr in summary.chars().chain(std::iter::repeat('-')).take(text_width) {
+                line_render.push((chr, theme.dim, theme.bg, highlight));
+            }
+            screen_view.push(line_render);
+            continue;
+        }
+        // the characters scrolled off to the left
+        let skipped = if file_data[line_y].len() >= window_line_x {
+            file_data[line_y][..window_line_x].chars().count()
+        } else {
+            0
         };
-        let mut highlight = mode == 'V' && is_line_highlighted(y + window_line_y, visual_y, cursor_y);
-        let mut fg_color = Color::White;
-        let mut bg_color = Color::Black;
+        let mut highlight = mode == 'V' && is_line_highlighted(line_y, visual_y, cursor_y);
         let mut x = 0;
-        let mut in_string = false;
-        let mut string_char: char = '\0';
-        let mut disregard_next = false;
         let search_len = search_string.len();
         let mut search_ranges: Vec<(usize, usize)> = vec![];
         for (index, _) in line.match_indices(&search_string) {
             search_ranges.push((index, index + search_len));
         }
-        for (chr_index, chr) in line_chars.enumerate() {
+        for (chr_index, chr) in line.chars().enumerate() {
             if mode == 'v' {
-                highlight = is_highlighted(x + window_line_x, y + window_line_y, visual_x, visual_y, cursor_x, cursor_y);
+                highlight = is_highlighted(x + window_line_x, line_y, visual_x, visual_y, cursor_x, cursor_y);
             }
-            if x >= comment_index {
-                fg_color = Color::Green;
+            let kind = kinds[y].get(skipped + chr_index).copied().unwrap_or(Kind::Text);
+            let (fg_color, bg_color) = if searching && search_ranges.iter().any(|&(start, end)| chr_index >= start && chr_index < end) {
+                (theme.search_fg, theme.search_bg)
             } else {
-                if in_string == true {
-                    fg_color = Color::Magenta;
-                }
-                if chr == string_char && string_char != '\0' && !disregard_next {
-                    in_string = !in_string;
-                    string_char = '\0';
-                } else if chr == '"' || chr == '\'' || chr == '`' {
-                    fg_color = Color::Magenta;
-                    if string_char == '\0' {
-                        in_string = !in_string;
-                        string_char = chr;
-                    }
-                } else if !in_string && (chr == '[' || chr == ']') {
-                    fg_color = Color::Green;
-                } else if !in_string && (chr == '{' || chr == '}') {
-                    fg_color = Color::Cyan;
-                } else if !in_string && (chr == '(' || chr == ')') {
-                    fg_color = Color::Yellow;
-                }
-                disregard_next = false;
-                if in_string && chr == '\\' {
-                    disregard_next = true;
-                }
-                if searching && search_ranges.iter().any(|&(start, end)| chr_index >= start && chr_index < end) {
-                    fg_color = Color::Black;
-                    bg_color = Color::Green;
-                } else {
-                    bg_color = Color::Black;
-                }
-            }
+                (syntax::get_color(kind, theme), theme.bg)
+            };
             line_render.push((chr, fg_color, bg_color, highlight));
-            fg_color = Color::White;
             x += 1;
         }
         if line.len() == 0 {
-            line_render.push((' ', Color::White, Color::Black, highlight));
+            line_render.push((' ', theme.fg, theme.bg, highlight));
         }
         screen_view.push(line_render);
-        y += 1;
     }
-    update_terminal(&prev_view, &screen_view, full_render);
-    execute!(stdout, MoveToRow(cursor_y as u16 - window_line_y as u16)).expect("Failed to move cursor");
-    let cursor_x_display: u16 = if file_data[cursor_y].len() == 0 {
+    screen_view
+}
+
+// the line numbers take 4 columns and a space
+pub fn get_gutter_width(number: bool) -> usize {
+    if number { 5 } else { 0 }
+}
+
+// cursor position relative to the top left of the window
+pub fn get_cursor_display(file_data: &LineRope, lines: &[usize], window_line_x: usize, cursor_x: usize, cursor_y: usize, gutter: usize) -> (usize, usize) {
+    let cursor_x_display = if file_data[cursor_y].is_empty() {
         0
     } else if cursor_x > file_data[cursor_y].len() {
-        (file_data[cursor_y].len() - 1) as u16
+        file_data[cursor_y].len() - 1
     } else {
-        cursor_x as u16 - window_line_x as u16
+        cursor_x
     };
-    execute!(stdout, MoveToColumn(cursor_x_display as u16 + 5)).expect("Failed to move cursor");
-    screen_view
+    // the row counts the lines drawn above it, a closed fold being one
+    (cursor_x_display.saturating_sub(window_line_x) + gutter, lines.iter().take_while(|y| **y < cursor_y).count())
 }
 
-pub fn starts_with_after_trim(my_string: &str, substring: &str) -> bool {
-    let trimmed_string = my_string.trim_start();
-    trimmed_string.starts_with(substring)
+pub fn get_command_line(mode: char, command_string: &str, search_string: &str, message: &str) -> String {
+    if mode == ':' {
+        format!(":{}", command_string)
+    } else if mode == '/' {
+        format!("/{}", search_string)
+    } else {
+        message.to_string()
+    }
 }
 
-pub fn remove_substring_and_space_after(my_string: &str, substring: &str) -> String {
-    if let Some(index) = my_string.find(substring) {
-        let mut result = String::with_capacity(my_string.len());
-        result.push_str(&my_string[..index]);
-        if let Some(next_char) = my_string.get(index + substring.len()..).and_then(|s| s.chars().next()) {
-            if next_char.is_whitespace() {
-                result.push_str(&my_string[index + substring.len() + next_char.len_utf8()..]);
-            } else {
-                result.push_str(&my_string[index + substring.len()..]);
-            }
-        } else {
-            result.push_str(&my_string[index + substring.len()..]);
-        }
-        result
-    } else {
-        String::from(my_string)
+// the open and close tokens of the comment a line is in, close is empty for a line comment
+fn get_line_comment_style<'a>(line: &str, line_comment: Option<&'a str>, block_comment: Option<(&'a str, &'a str)>) -> Option<(&'a str, &'a str)> {
+    let trimmed = line.trim();
+    if let Some(token) = line_comment.filter(|token| trimmed.starts_with(token)) {
+        return Some((token, ""));
     }
+    block_comment.filter(|(open, close)| {
+        trimmed.len() >= open.len() + close.len() && trimmed.starts_with(open) && trimmed.ends_with(close)
+    })
 }
 
-pub fn toggle_comments_in_visual(file_data: &mut [String], comment_string: &str, cursor_y: usize, visual_y: usize) {
-    let (begin_y, end_y) = if cursor_y <= visual_y {
-        (cursor_y, visual_y)
+fn comment_line(line: &str, open: &str, close: &str, indent: usize) -> String {
+    if close.is_empty() {
+        format!("{}{} {}", &line[..indent], open, &line[indent..])
     } else {
-        (visual_y, cursor_y)
-    };
-    let mut lowest_indent = 0;
-    let mut are_all_commented = true;
-    for i in begin_y..end_y+1 {
-        let current_indent = count_leading_spaces(&file_data[i].clone());
-        if lowest_indent > current_indent {
-            lowest_indent = current_indent;
-        }
-        if !starts_with_after_trim(&file_data[i].clone(), comment_string) && file_data[i].len() != 0 {
-            are_all_commented = false;
+        format!("{}{} {} {}", &line[..indent], open, line[indent..].trim_end(), close)
+    }
+}
+
+fn uncomment_line(line: &str, open: &str, close: &str) -> String {
+    let indent = count_leading_spaces(line);
+    let mut body = &line[indent..];
+    body = body.strip_prefix(open).unwrap_or(body);
+    body = body.strip_prefix(' ').unwrap_or(body);
+    if !close.is_empty() {
+        body = body.trim_end();
+        body = body.strip_suffix(close).unwrap_or(body);
+        body = body.strip_suffix(' ').unwrap_or(body);
+    }
+    format!("{}{}", &line[..indent], body)
+}
+
+// uncomments the lines if every non-blank one is commented, in either style, and comments them at their
+// lowest indent otherwise; block comments wrap each line and are used when there is no line comment
+pub fn toggle_comments(
+    file_data: &mut LineRope,
+    line_comment: Option<&str>,
+    block_comment: Option<(&str, &str)>,
+    begin_y: usize,
+    end_y: usize,
+) {
+    let lines: Vec<usize> = (begin_y..end_y + 1).filter(|y| !file_data[*y].trim().is_empty()).collect();
+    let styles: Vec<Option<(&str, &str)>> = lines
+        .iter()
+        .map(|y| get_line_comment_style(&file_data[*y], line_comment, block_comment))
+        .collect();
+    if styles.iter().all(|style| style.is_some()) {
+        for (y, style) in lines.iter().zip(styles) {
+            if let Some((open, close)) = style {
+                file_data[*y] = uncomment_line(&file_data[*y], open, close);
+            }
         }
+        return;
     }
-    for i in begin_y..end_y+1 {
-        if are_all_commented {
-            file_data[i] = toggle_comment(file_data[i].clone(), comment_string);
-        } else {
-            file_data[i] = comment_at_index(file_data[i].clone(), comment_string, lowest_indent);
+    let (open, close) = match (line_comment, block_comment) {
+        (Some(token), _) => (token, ""),
+        (None, Some(tokens)) => tokens,
+        (None, None) => return,
+    };
+    let indent = lines.iter().map(|y| count_leading_spaces(&file_data[*y])).min().unwrap_or(0);
+    for (y, style) in lines.iter().zip(styles) {
+        // block comments do not nest, so a line already in one stays as it is
+        if !close.is_empty() && style.is_some() {
+            continue;
         }
+        file_data[*y] = comment_line(&file_data[*y], open, close, indent);
     }
 }
 
-pub fn get_prev_occurrence(file_data: &[String], mut cursor_x: usize, mut cursor_y: usize, search_string: &str) -> Option<(usize, usize)> {
+pub fn get_prev_occurrence(file_data: &LineRope, mut cursor_x: usize, mut cursor_y: usize, search_string: &str) -> Option<(usize, usize)> {
     let num_lines = file_data.len();
     let initial_y = cursor_y;
     let mut has_looped = false;
@@ -308,7 +354,7 @@ pub fn get_prev_occurrence(file_data: &[String], mut cursor_x: usize, mut cursor
     }
 }
 
-pub fn find_next_occurrence(file_data: &[String], mut cursor_x: usize, mut cursor_y: usize, search_string: &str) -> Option<(usize, usize)> {
+pub fn find_next_occurrence(file_data: &LineRope, mut cursor_x: usize, mut cursor_y: usize, search_string: &str) -> Option<(usize, usize)> {
     let num_lines = file_data.len();
     let initial_pos = cursor_y;
     let mut has_looped = false;
@@ -330,139 +376,132 @@ pub fn find_next_occurrence(file_data: &[String], mut cursor_x: usize, mut curso
     }
 }
 
-pub fn comment_at_index(mut line: String, comment_string: &str, index: usize) -> String {
-    if line.len() != 0 {
-        line.insert_str(index, " ");
-        line.insert_str(index, comment_string);
-        line
-    } else {
-        line
+// whether a key starts a change to the text, so a read only buffer can refuse it before anything runs
+pub fn is_edit_key(mode: char, prev_keys: &str, code: KeyCode, modifiers: KeyModifiers) -> bool {
+    let control = modifiers.contains(KeyModifiers::CONTROL);
+    match (mode, prev_keys, code) {
+        ('i', _, code) => code != KeyCode::Esc,
+        ('n', "", KeyCode::Char('r')) if control => true,
+        (_, _, _) if control => false,
+        ('n', "", KeyCode::Char(c)) => "aAiIoOsxpP<>cdru!.".contains(c),
+        ('v' | 'V', "", KeyCode::Char(c)) => "cdx<>".contains(c),
+        ('n' | 'v' | 'V', "g", KeyCode::Char('c')) => true,
+        _ => false,
     }
 }
 
-pub fn toggle_comment(mut line: String, comment_string: &str) -> String {
-    if line.len() != 0 {
-        if !starts_with_after_trim(&line, comment_string) {
-            let start = count_leading_spaces(&line);
-            line.insert_str(start, " ");
-            line.insert_str(start, comment_string);
-            line
-        } else {
-            remove_substring_and_space_after(&line, comment_string)
-        }
-    } else {
-        line
-    }
-}
-
-pub fn get_comment_string(file_name: &str) -> Option<&str> {
-    if let Some(extension) = file_name.split('.').last() {
-        match extension {
-            "scss" => Some("/*"),
-            "css" => Some("/*"),
-            "html" => Some("<!--"),
-            "swift" => Some("//"),
-            "java" => Some("//"),
-            "js" => Some("//"),
-            "jsx" => Some("//"),
-            "ts" => Some("//"),
-            "tsx" => Some("//"),
-            "rs" => Some("//"),
-            "c" => Some("//"),
-            "cpp" => Some("//"),
-            "sh" => Some("#"),
-            "py" => Some("#"),
-            "rb" => Some("#"),
-            "pl" => Some("#"),
-            "php" => Some("//"),
-            "go" => Some("//"),
-            "kotlin" => Some("//"),
-            "groovy" => Some("//"),
-            "scala" => Some("//"),
-            "perl" => Some("#"),
-            "lua" => Some("--"),
-            "coffee" => Some("#"),
-            "dart" => Some("//"),
-            "h" => Some("/*"),
-            "hpp" => Some("/*"),
-            "m" => Some("//"),
-            "mm" => Some("//"),
-            "sql" => Some("--"),
-            "vb" => Some("'"),
-            "xml" => Some("<!--"),
-            "yaml" => Some("#"),
-            "dockerfile" => Some("#"),
-            "makefile" => Some("#"),
-            "bat" => Some("REM"),
-            "powershell" => Some("#"),
-            "ini" => Some(";"),
-            "cmake" => Some("#"),
-            "m4" => Some("dnl"),
-            "fish" => Some("#"),
-            "haskell" => Some("--"),
-            "elixir" => Some("#"),
-            "nim" => Some("#"),
-            "erl" => Some("%"),
-            "ex" => Some("#"),
-            "f" => Some("!"),
-            "fort" => Some("C"),
-            "f90" => Some("!"),
-            "f95" => Some("!"),
-            "hs" => Some("--"),
-            "ml" => Some("(*"),
-            "mli" => Some("(*"),
-            "scm" => Some(";;"),
-            "ss" => Some(";;"),
-            "tcl" => Some("#"),
-            "vim" => Some("\""),
-            _ => None,
+pub fn log_command(code: KeyCode, modifiers: KeyModifiers, last_command: &mut Vec<(KeyCode, KeyModifiers)>, recording: bool) {
+    if recording {
+        last_command.push((code, modifiers));
+    }
+}
+
+// runs a command through sh with input on stdin, failing with the first line of stderr if it exits non-zero
+pub fn run_shell(command: &str, input: &str) -> error::Result<String> {
+    let shell_error = |reason: String| error::EditorError::Shell(command.to_string(), reason);
+    let mut child = Command::new("sh")
+        .args(["-c", command])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| shell_error(err.to_string()))?;
+    // written from a thread so a command that writes before reading all its input cannot deadlock
+    let writer = child.stdin.take().map(|mut stdin| {
+        let input = input.to_string();
+        thread::spawn(move || {
+            let _ = stdin.write_all(input.as_bytes());
+        })
+    });
+    let output = child.wait_with_output().map_err(|err| shell_error(err.to_string()))?;
+    if let Some(writer) = writer {
+        let _ = writer.join();
+    }
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let reason = match stderr.lines().find(|line| !line.trim().is_empty()) {
+            Some(line) => line.trim().to_string(),
+            None => output.status.to_string(),
+        };
+        return Err(shell_error(reason));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).to_string())
+}
+
+// the non-blank lines around y for ip, plus the blank lines after them for ap
+pub fn get_paragraph(file_data: &LineRope, y: usize, around: bool) -> (usize, usize) {
+    let blank = |line: usize| file_data[line].trim().is_empty();
+    let is_blank = blank(y);
+    let mut start = y;
+    while start > 0 && blank(start - 1) == is_blank {
+        start -= 1;
+    }
+    let mut end = y;
+    while end + 1 < file_data.len() && blank(end + 1) == is_blank {
+        end += 1;
+    }
+    if around && !is_blank {
+        while end + 1 < file_data.len() && blank(end + 1) {
+            end += 1;
         }
-    } else {
-        None
     }
+    (start, end)
 }
 
-pub fn find_substring(line: &str, substring: &str) -> Option<usize> {
-    if let Some(index) = line.find(substring) {
-        Some(index)
-    } else {
-        None
+// lines covered by the motion after an operator like ! or gc, None while it is incomplete or not a line motion;
+// the operator's last key again, as in !! or gcc, is the current line
+pub fn get_motion_lines(file_data: &LineRope, cursor_y: usize, motion: &str, line_key: &str) -> Option<(usize, usize)> {
+    let last = file_data.len() - 1;
+    match motion {
+        motion if motion == line_key => Some((cursor_y, cursor_y)),
+        "j" => Some((cursor_y, (cursor_y + 1).min(last))),
+        "k" => Some((cursor_y.saturating_sub(1), cursor_y)),
+        "G" => Some((cursor_y, last)),
+        "gg" => Some((0, cursor_y)),
+        "ip" => Some(get_paragraph(file_data, cursor_y, false)),
+        "ap" => Some(get_paragraph(file_data, cursor_y, true)),
+        _ => None,
     }
 }
 
-pub fn log_command(code: KeyCode, modifiers: KeyModifiers, last_command: &mut Vec<(KeyCode, KeyModifiers)>, recording: bool) {
-    if recording {
-        last_command.push((code, modifiers));
+// the screen goes to the terminal even when stdout is a pipe, as in git diff | editor - | less
+pub fn get_terminal_output() -> Box<dyn Write> {
+    if stdout().is_terminal() {
+        return Box::new(stdout());
+    }
+    match OpenOptions::new().write(true).open("/dev/tty") {
+        Ok(tty) => Box::new(tty),
+        Err(_) => Box::new(stdout()),
+    }
+}
+
+// for --stdout, once the terminal is restored
+pub fn print_lines(data: &LineRope) -> io::Result<()> {
+    let mut stdout = stdout().lock();
+    for line in data.iter() {
+        writeln!(stdout, "{}", line)?;
     }
+    stdout.flush()
 }
 
 pub fn quit_terminal() {
-    let mut stdout = stdout();
+    let mut stdout = get_terminal_output();
     execute!(stdout, LeaveAlternateScreen).expect("Failed to leave alternate screen");
     disable_raw_mode().expect("Failed to disable raw mode");
 }
 
-pub fn save_to_file_no_snapshot(data: &mut Vec<String>, file_path: &str) {
-    if let Ok(mut file) = File::create(file_path) {
-        for line in data {
-            let _ = file.write_all(line.as_bytes());
-            let _ = file.write_all(b"\n");
-        }
-    } else {
-        println!("Failed to save the file");
-    }
-}
-
-pub fn save_to_file(data: &mut Vec<String>, file_path: &str, diff_history: &mut diffhist::DiffHistory, pos: (usize, usize)) {
-    diff_history.make_change(data.clone(), pos);
-    if let Ok(mut file) = File::create(file_path) {
-        for line in data {
-            let _ = file.write_all(line.as_bytes());
-            let _ = file.write_all(b"\n");
-        }
-    } else {
-        println!("Failed to save the file");
+pub fn save_to_file_no_snapshot(data: &LineRope, file_path: &str, line_ending: &str) -> error::Result<()> {
+    let save_error = |err: io::Error| {
+        let missing_dir = err.kind() == io::ErrorKind::NotFound && !Path::new(file_path).exists();
+        let hint = if missing_dir { " (:w ++p creates the directory)" } else { "" };
+        error::EditorError::Io(format!("Failed to save {}{}", file_path, hint), err)
+    };
+    let mut file = File::create(file_path).map_err(save_error)?;
+    for line in data.iter() {
+        file.write_all(line.as_bytes()).map_err(save_error)?;
+        file.write_all(line_ending.as_bytes()).map_err(save_error)?;
     }
+    Ok(())
 }
 
 pub fn get_in_word(string: &str, cursor_x: usize) -> Option<(usize, usize)> {
@@ -496,7 +535,7 @@ pub fn get_in_word(string: &str, cursor_x: usize) -> Option<(usize, usize)> {
     word_start.zip(word_end)
 }
 
-pub fn right_insert(file_data: &[String], cursor_x: usize, cursor_y: usize) -> usize {
+pub fn right_insert(file_data: &LineRope, cursor_x: usize, cursor_y: usize) -> usize {
     if cursor_x + 1 <= file_data[cursor_y].len() {
         cursor_x + 1
     } else {
@@ -504,7 +543,7 @@ pub fn right_insert(file_data: &[String], cursor_x: usize, cursor_y: usize) -> u
     }
 }
 
-pub fn right(file_data: &[String], cursor_x: usize, cursor_y: usize) -> usize {
+pub fn right(file_data: &LineRope, cursor_x: usize, cursor_y: usize) -> usize {
     if cursor_x + 1 < file_data[cursor_y].len() {
         cursor_x + 1
     } else {
@@ -520,7 +559,7 @@ pub fn left(cursor_x: usize) -> usize {
     }
 }
 
-pub fn down(file_data: &[String], cursor_y: usize) -> usize {
+pub fn down(file_data: &LineRope, cursor_y: usize) -> usize {
     if cursor_y < file_data.len() - 1 {
         cursor_y + 1
     } else {
@@ -536,7 +575,7 @@ pub fn up(cursor_y: usize) -> usize {
     }
 }
 
-pub fn get_index_prev_word(file_data: &[String], mut cursor_x: usize, cursor_y: usize) -> usize {
+pub fn get_index_prev_word(file_data: &LineRope, mut cursor_x: usize, cursor_y: usize) -> usize {
     let cursor_start = cursor_x;
     let line = &file_data[cursor_y];
     if cursor_x == 0 {
@@ -554,7 +593,7 @@ pub fn get_index_prev_word(file_data: &[String], mut cursor_x: usize, cursor_y:
     prev_word_start + 1
 }
 
-pub fn get_index_prev_alpha_word(file_data: &[String], cursor_x: usize, cursor_y: usize) -> usize {
+pub fn get_index_prev_alpha_word(file_data: &LineRope, cursor_x: usize, cursor_y: usize) -> usize {
     let line = &file_data[cursor_y];
     let line_slice = &line[..cursor_x];
     if let Some(prev_word_start) = line_slice.char_indices().rev().find(|(_, c)| c.is_alphanumeric() || *c == '_').map(|(i, _)| i) {
@@ -564,7 +603,7 @@ pub fn get_index_prev_alpha_word(file_data: &[String], cursor_x: usize, cursor_y
     }
 }
 
-pub fn get_index_prev_non_word(file_data: &[String], cursor_x: usize, cursor_y: usize) -> usize {
+pub fn get_index_prev_non_word(file_data: &LineRope, cursor_x: usize, cursor_y: usize) -> usize {
     let line = &file_data[cursor_y];
     let line_slice = &line[..cursor_x];
     if let Some(prev_word_start) = line_slice.char_indices().rev().find(|(_, c)| !c.is_alphanumeric() && *c != '_').map(|(i, _)| i) {
@@ -574,7 +613,7 @@ pub fn get_index_prev_non_word(file_data: &[String], cursor_x: usize, cursor_y:
     }
 }
 
-pub fn get_index_next_word(file_data: &[String], mut cursor_x: usize, cursor_y: usize) -> usize {
+pub fn get_index_next_word(file_data: &LineRope, mut cursor_x: usize, cursor_y: usize) -> usize {
     let cursor_start = cursor_x;
     let line = &file_data[cursor_y];
     let current = line.chars().nth(cursor_x);
@@ -592,7 +631,7 @@ pub fn get_index_next_word(file_data: &[String], mut cursor_x: usize, cursor_y:
     }
 }
 
-pub fn get_next_empty_line(file_data: &[String], cursor_y: usize) -> usize {
+pub fn get_next_empty_line(file_data: &LineRope, cursor_y: usize) -> usize {
     for i in cursor_y+1..file_data.len() {
         if file_data[i] == "" {
             return i;
@@ -601,7 +640,7 @@ pub fn get_next_empty_line(file_data: &[String], cursor_y: usize) -> usize {
     return file_data.len() - 1;
 }
 
-pub fn get_prev_empty_line(file_data: &[String], cursor_y: usize) -> usize {
+pub fn get_prev_empty_line(file_data: &LineRope, cursor_y: usize) -> usize {
     for i in (0..cursor_y).rev() {
         if file_data[i] == "" {
             return i;
@@ -610,7 +649,7 @@ pub fn get_prev_empty_line(file_data: &[String], cursor_y: usize) -> usize {
     return 0;
 }
 
-pub fn get_index_next_non_word(file_data: &[String], cursor_x: usize, cursor_y: usize) -> usize {
+pub fn get_index_next_non_word(file_data: &LineRope, cursor_x: usize, cursor_y: usize) -> usize {
     let line = &file_data[cursor_y];
     let line_slice = &line[cursor_x..];
     if let Some(next_word_start) = line_slice.find(|c: char| !c.is_alphanumeric() && c != '_') {
@@ -620,11 +659,11 @@ pub fn get_index_next_non_word(file_data: &[String], cursor_x: usize, cursor_y:
     }
 }
 
-pub fn set_cursor_end(file_data: &[String], cursor_y: usize) -> usize {
+pub fn set_cursor_end(file_data: &LineRope, cursor_y: usize) -> usize {
     file_data[cursor_y].len()
 }
 
-pub fn prevent_cursor_end(file_data: &[String], cursor_x: usize, cursor_y: usize) -> usize {
+pub fn prevent_cursor_end(file_data: &LineRope, cursor_x: usize, cursor_y: usize) -> usize {
     if file_data[cursor_y].len() != 0 && cursor_x >= file_data[cursor_y].len() {
         file_data[cursor_y].len() - 1
     } else {
@@ -632,7 +671,7 @@ pub fn prevent_cursor_end(file_data: &[String], cursor_x: usize, cursor_y: usize
     }
 }
 
-pub fn reset_cursor_end(file_data: &[String], cursor_x: usize, cursor_y: usize) -> usize {
+pub fn reset_cursor_end(file_data: &LineRope, cursor_x: usize, cursor_y: usize) -> usize {
     if file_data[cursor_y].len() == 0 {
         0
     } else if cursor_x > file_data[cursor_y].len() - 1 {
@@ -642,17 +681,13 @@ pub fn reset_cursor_end(file_data: &[String], cursor_x: usize, cursor_y: usize)
     }
 }
 
-pub fn increase_indent(string: String) -> String {
-    if string == "" {
-        "    ".to_string()
-    } else {
-        format!("    {}", string)
-    }
+pub fn increase_indent(string: String, tabstop: usize) -> String {
+    format!("{}{}", " ".repeat(tabstop), string)
 }
 
-pub fn reduce_indent(string: String) -> String {
-    if string.chars().take(4).all(|c| c == ' ') {
-        string.chars().skip(4).collect()
+pub fn reduce_indent(string: String, tabstop: usize) -> String {
+    if string.chars().take(tabstop).all(|c| c == ' ') {
+        string.chars().skip(tabstop).collect()
     } else {
         string.trim_start().to_string()
     }
@@ -666,32 +701,33 @@ pub fn normalize(cursor: usize, visual: usize) -> (usize, usize) {
     }
 }
 
-pub fn increase_indent_visual(file_data: &mut Vec<String>, cursor_y: usize, visual_y: usize) {
+pub fn increase_indent_visual(file_data: &mut LineRope, cursor_y: usize, visual_y: usize, tabstop: usize) {
     let (begin_y, end_y) = if cursor_y <= visual_y {
         (cursor_y, visual_y)
     } else {
         (visual_y, cursor_y)
     };
     for i in begin_y..end_y+1 {
-        file_data[i] = increase_indent(file_data[i].clone());
+        file_data[i] = increase_indent(file_data[i].clone(), tabstop);
     }
 }
 
-pub fn reduce_indent_visual(file_data: &mut Vec<String>, cursor_y: usize, visual_y: usize) {
+pub fn reduce_indent_visual(file_data: &mut LineRope, cursor_y: usize, visual_y: usize, tabstop: usize) {
     let (begin_y, end_y) = if cursor_y <= visual_y {
         (cursor_y, visual_y)
     } else {
         (visual_y, cursor_y)
     };
     for i in begin_y..end_y+1 {
-        file_data[i] = reduce_indent(file_data[i].clone());
+        file_data[i] = reduce_indent(file_data[i].clone(), tabstop);
     }
 }
 
-pub fn calc_window_lines(file_data: &[String], window_line_x: usize, window_line_y: usize, cursor_x: usize, cursor_y: usize) -> (usize, usize) {
-    let terminal_size = size().unwrap();
-    let term_height = terminal_size.1 as usize;
-    let term_width = terminal_size.0 as usize;
+// keeps the cursor in view, with scrolloff lines around it where the file allows
+pub fn calc_window_lines(file_data: &LineRope, window: &Window, gutter: usize, scrolloff: usize) -> (usize, usize) {
+    let (window_line_x, window_line_y, cursor_x, cursor_y) = (window.window_line_x, window.window_line_y, window.cursor_x, window.cursor_y);
+    let term_height = window.height.max(1);
+    let text_width = window.width.saturating_sub(gutter).max(2);
     let mut x = window_line_x;
     let mut y = window_line_y;
     let cursor_display_x = if file_data.len() == 0 {
@@ -701,17 +737,20 @@ pub fn calc_window_lines(file_data: &[String], window_line_x: usize, window_line
     } else {
         cursor_x
     };
-    if window_line_x + (term_width - 6) <= cursor_display_x {
-        x = cursor_display_x - (term_width - 6);
+    if window_line_x + (text_width - 1) <= cursor_display_x {
+        x = cursor_display_x - (text_width - 1);
     }
     if window_line_x > cursor_display_x {
         x = cursor_display_x;
     }
-    if window_line_y > cursor_y {
-        y = cursor_y;
+    let scrolloff = scrolloff.min((term_height - 1) / 2);
+    let top = cursor_y.saturating_sub(scrolloff);
+    let bottom = (cursor_y + scrolloff).min(file_data.len().saturating_sub(1)).max(cursor_y);
+    if window_line_y > top {
+        y = top;
     }
-    if window_line_y + term_height <= cursor_y {
-        y = cursor_y - term_height + 1;
+    if window_line_y + term_height <= bottom {
+        y = bottom - term_height + 1;
     }
     (x, y)
 }
@@ -738,8 +777,8 @@ pub fn get_cursor_after_visual(cursor: usize, visual: usize) -> usize {
     }
 }
 
-pub fn paste_before(file_data: &mut Vec<String>, cursor_x: usize, cursor_y: usize) {
-    let mut clip = get_clipboard_content();
+pub fn paste_before(file_data: &mut LineRope, cursor_x: usize, cursor_y: usize, clipboard: &str) -> error::Result<()> {
+    let mut clip = get_clipboard_content(clipboard)?;
     if clip.starts_with("\n") {
         clip.remove(0);
         let lines: Vec<&str> = clip.split('\n').collect();
@@ -764,10 +803,11 @@ pub fn paste_before(file_data: &mut Vec<String>, cursor_x: usize, cursor_y: usiz
             }
         }
     }
+    Ok(())
 }
 
-pub fn paste_after(file_data: &mut Vec<String>, cursor_x: usize, cursor_y: usize) {
-    let mut clip = get_clipboard_content();
+pub fn paste_after(file_data: &mut LineRope, cursor_x: usize, cursor_y: usize, clipboard: &str) -> error::Result<()> {
+    let mut clip = get_clipboard_content(clipboard)?;
     if clip.starts_with("\n") {
         clip.remove(0);
         let lines: Vec<&str> = clip.split('\n').collect();
@@ -792,79 +832,73 @@ pub fn paste_after(file_data: &mut Vec<String>, cursor_x: usize, cursor_y: usize
             }
         }
     }
+    Ok(())
 }
 
-pub fn copy_in_visual(
-    file_data: &mut Vec<String>,
-    cursor_x: usize,
-    cursor_y: usize,
-    visual_x: usize,
-    visual_y: usize,
-    mode: char
-    ) -> (usize, usize) {
-    let mut clipboard: String = if mode == 'V' {"\n".to_string()} else {"".to_string()};
-    let (begin_y, end_y) = if cursor_y <= visual_y {
-        (cursor_y, visual_y)
+// the two ends of a selection as (x, y), the one that comes first in the text first; the columns are not
+// ordered on their own, as a selection can go up and to the right
+pub fn order_selection(cursor_x: usize, cursor_y: usize, visual_x: usize, visual_y: usize) -> ((usize, usize), (usize, usize)) {
+    if (cursor_y, cursor_x) <= (visual_y, visual_x) {
+        ((cursor_x, cursor_y), (visual_x, visual_y))
     } else {
-        (visual_y, cursor_y)
-    };
-    let (begin_x, end_x) = if cursor_x <= visual_x {
-        (cursor_x, visual_x)
-    } else {
-        (visual_x, cursor_x)
-    };
+        ((visual_x, visual_y), (cursor_x, cursor_y))
+    }
+}
+
+pub fn get_visual_text(file_data: &LineRope, cursor_x: usize, cursor_y: usize, visual_x: usize, visual_y: usize, mode: char) -> String {
+    let mut text: String = if mode == 'V' {"\n".to_string()} else {"".to_string()};
+    let ((begin_x, begin_y), (end_x, end_y)) = order_selection(cursor_x, cursor_y, visual_x, visual_y);
     if mode == 'V' {
         for i in begin_y..end_y {
-            clipboard += &file_data[i];
-            clipboard += "\n";
+            text += &file_data[i];
+            text += "\n";
         }
-        clipboard += &file_data[end_y];
+        text += &file_data[end_y];
     } else if mode == 'v' {
         if begin_y == end_y {
-            clipboard += &file_data[begin_y][begin_x..end_x+1];
+            text += &file_data[begin_y][begin_x..end_x+1];
         } else {
-            clipboard += &file_data[begin_y][begin_x..];
-            for i in begin_y..end_y-1 {
-                clipboard += "\n";
-                clipboard += &file_data[i];
+            text += &file_data[begin_y][begin_x..];
+            for i in begin_y + 1..end_y {
+                text += "\n";
+                text += &file_data[i];
             }
-            clipboard += "\n";
+            text += "\n";
             if file_data[end_y].len() > 0 {
-                clipboard += &file_data[end_y][..end_x+1];
+                text += &file_data[end_y][..end_x+1];
             }
         }
     }
-    copy_to_clipboard(&clipboard).expect("failed copy to clipboard");
-    (begin_x, begin_y)
+    text
 }
 
-pub fn delete_in_visual(file_data: &mut Vec<String>, cursor_x: usize, cursor_y: usize, visual_x: usize, visual_y: usize, mode: char) -> (usize, usize) {
-    let (begin_y, end_y) = if cursor_y <= visual_y {
-        (cursor_y, visual_y)
-    } else {
-        (visual_y, cursor_y)
-    };
-    let (begin_x, end_x) = if cursor_x <= visual_x {
-        (cursor_x, visual_x)
-    } else {
-        (visual_x, cursor_x)
-    };
+pub fn copy_in_visual(
+    file_data: &mut LineRope,
+    cursor_x: usize,
+    cursor_y: usize,
+    visual_x: usize,
+    visual_y: usize,
+    mode: char,
+    provider: &str,
+    ) -> error::Result<(usize, usize)> {
+    copy_to_clipboard(&get_visual_text(file_data, cursor_x, cursor_y, visual_x, visual_y, mode), provider)?;
+    Ok(order_selection(cursor_x, cursor_y, visual_x, visual_y).0)
+}
+
+pub fn delete_in_visual(file_data: &mut LineRope, cursor_x: usize, cursor_y: usize, visual_x: usize, visual_y: usize, mode: char) -> (usize, usize) {
+    let ((begin_x, begin_y), (end_x, end_y)) = order_selection(cursor_x, cursor_y, visual_x, visual_y);
     if mode == 'V' {
-        for _ in begin_y..=end_y {
-            file_data.remove(begin_y);
-        }
+        file_data.remove_lines(begin_y..end_y + 1);
     } else if mode == 'v' {
         if begin_y == end_y {
             file_data[begin_y].drain(begin_x..end_x+1);
         } else {
             file_data[begin_y].drain(begin_x..);
-            for _ in begin_y..end_y-1 {
-                file_data.remove(begin_y + 1);
-            }
+            file_data.remove_lines(begin_y + 1..end_y);
             if file_data[begin_y+1].len() > 0 {
                 file_data[begin_y+1].drain(..end_x+1);
-                let joined_lines = file_data[begin_y..begin_y+2].join("");
-                file_data[begin_y] = joined_lines.to_string();
+                let joined_lines = file_data[begin_y].clone() + &file_data[begin_y + 1];
+                file_data[begin_y] = joined_lines;
             }
             file_data.remove(begin_y + 1);
         }
@@ -875,15 +909,13 @@ pub fn delete_in_visual(file_data: &mut Vec<String>, cursor_x: usize, cursor_y:
     (begin_x, begin_y)
 }
 
-pub fn delete_in_visual_and_insert(file_data: &mut Vec<String>, cursor_y: usize, visual_y: usize) {
+pub fn delete_in_visual_and_insert(file_data: &mut LineRope, cursor_y: usize, visual_y: usize) {
     let (begin, end) = if cursor_y <= visual_y {
         (cursor_y, visual_y)
     } else {
         (visual_y, cursor_y)
     };
-    for _ in begin..=end {
-        file_data.remove(begin);
-    }
+    file_data.remove_lines(begin..end + 1);
     file_data.insert(begin, "".to_string());
     if file_data.len() == 0 {
         file_data.insert(0, "".to_string());
@@ -904,3 +936,63 @@ pub fn count_leading_spaces(input: &str) -> usize {
     leading_spaces
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rope(lines: &[&str]) -> LineRope {
+        LineRope::from(lines.iter().map(|line| line.to_string()).collect::<Vec<String>>())
+    }
+
+    #[test]
+    fn knows_which_keys_change_the_text() {
+        let none = KeyModifiers::NONE;
+        let control = KeyModifiers::CONTROL;
+        for key in ['i', 'a', 'o', 'x', 'p', 'd', 'c', 'u', 'r', '.', '>'] {
+            assert!(is_edit_key('n', "", KeyCode::Char(key), none), "{}", key);
+        }
+        for key in ['j', 'y', 'v', 'g', 'z', ':', '/', 'n'] {
+            assert!(!is_edit_key('n', "", KeyCode::Char(key), none), "{}", key);
+        }
+        assert!(is_edit_key('n', "", KeyCode::Char('r'), control));
+        assert!(!is_edit_key('n', "", KeyCode::Char('d'), control));
+        assert!(!is_edit_key('n', "y", KeyCode::Char('i'), none));
+        assert!(!is_edit_key('n', "z", KeyCode::Char('a'), none));
+        assert!(is_edit_key('n', "g", KeyCode::Char('c'), none));
+        assert!(is_edit_key('V', "", KeyCode::Char('d'), none));
+        assert!(!is_edit_key('v', "", KeyCode::Char('y'), none));
+        assert!(!is_edit_key('v', "", KeyCode::Char('i'), none));
+        assert!(is_edit_key('i', "", KeyCode::Char('x'), none));
+        assert!(!is_edit_key('i', "", KeyCode::Esc, none));
+        assert!(!is_edit_key(':', "", KeyCode::Char('x'), none));
+    }
+
+    #[test]
+    fn orders_a_selection_by_position() {
+        assert_eq!(order_selection(1, 2, 5, 0), ((5, 0), (1, 2)));
+        assert_eq!(order_selection(5, 0, 1, 2), ((5, 0), (1, 2)));
+        assert_eq!(order_selection(4, 1, 2, 1), ((2, 1), (4, 1)));
+    }
+
+    #[test]
+    fn gets_the_text_of_a_selection_going_up_and_right() {
+        let file_data = rope(&["abcdef", "middle", "xyz"]);
+        // the cursor at the end of the selection is on the last line but left of where it started
+        assert_eq!(get_visual_text(&file_data, 1, 2, 4, 0, 'v'), "ef\nmiddle\nxy");
+        assert_eq!(get_visual_text(&file_data, 4, 0, 1, 2, 'v'), "ef\nmiddle\nxy");
+        assert_eq!(get_visual_text(&file_data, 4, 0, 1, 1, 'v'), "ef\nmi");
+        assert_eq!(get_visual_text(&file_data, 3, 0, 1, 0, 'v'), "bcd");
+        assert_eq!(get_visual_text(&file_data, 5, 2, 0, 1, 'V'), "\nmiddle\nxyz");
+    }
+
+    #[test]
+    fn deletes_a_selection_going_up_and_right() {
+        let mut file_data = rope(&["abcdef", "middle", "xyz"]);
+        assert_eq!(delete_in_visual(&mut file_data, 1, 2, 4, 0, 'v'), (4, 0));
+        assert_eq!(file_data.to_vec(), ["abcdz"]);
+        let mut file_data = rope(&["abcdef", "middle", "xyz"]);
+        assert_eq!(delete_in_visual(&mut file_data, 0, 1, 3, 0, 'V'), (3, 0));
+        assert_eq!(file_data.to_vec(), ["xyz"]);
+    }
+}
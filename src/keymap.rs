@@ -0,0 +1,347 @@
+use crossterm::event::{KeyCode, KeyModifiers};
+
+pub type Key = (KeyCode, KeyModifiers);
+
+// how many mapped keys one typed key may expand to before it counts as a loop
+pub const MAX_MAP_DEPTH: usize = 1000;
+
+// the ex commands and config sections that define mappings, with their modes and whether they remap
+pub const MAP_COMMANDS: &[(&str, &str, bool)] = &[
+    ("map", "nv", true),
+    ("noremap", "nv", false),
+    ("nmap", "n", true),
+    ("nnoremap", "n", false),
+    ("imap", "i", true),
+    ("inoremap", "i", false),
+    ("vmap", "v", true),
+    ("vnoremap", "v", false),
+];
+
+pub const UNMAP_COMMANDS: &[(&str, &str)] = &[("unmap", "nv"), ("nunmap", "n"), ("iunmap", "i"), ("vunmap", "v")];
+
+pub struct Mapping {
+    pub mode: char,
+    pub lhs: Vec<Key>,
+    pub rhs: Vec<Key>,
+    pub recursive: bool,
+}
+
+pub struct Keymap {
+    pub mappings: Vec<Mapping>,
+    // typed keys that start a longer mapping, waiting for the next key or the timeout
+    pub pending: Vec<Key>,
+}
+
+impl Keymap {
+    pub fn new() -> Self {
+        Keymap {
+            mappings: Vec::new(),
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn map(&mut self, mode: char, lhs: Vec<Key>, rhs: Vec<Key>, recursive: bool) {
+        self.unmap(mode, &lhs);
+        self.mappings.push(Mapping { mode, lhs, rhs, recursive });
+    }
+
+    pub fn unmap(&mut self, mode: char, lhs: &[Key]) -> bool {
+        let len = self.mappings.len();
+        self.mappings.retain(|mapping| !(mapping.mode == mode && is_same_keys(&mapping.lhs, lhs)));
+        self.mappings.len() != len
+    }
+
+    // takes a typed key and returns the keys to run now, each with whether it may be mapped again
+    pub fn feed(&mut self, mode: char, key: Key) -> Vec<(Key, bool)> {
+        self.pending.push(key);
+        self.resolve(mode, false)
+    }
+
+    // the pending keys once the user stopped typing
+    pub fn flush(&mut self, mode: char) -> Vec<(Key, bool)> {
+        self.resolve(mode, true)
+    }
+
+    fn resolve(&mut self, mode: char, timed_out: bool) -> Vec<(Key, bool)> {
+        if self.pending.is_empty() {
+            return Vec::new();
+        }
+        let mappings: Vec<&Mapping> = self.mappings.iter().filter(|mapping| mapping.mode == mode).collect();
+        let is_prefix = mappings
+            .iter()
+            .any(|mapping| mapping.lhs.len() > self.pending.len() && is_same_keys(&mapping.lhs[..self.pending.len()], &self.pending));
+        if is_prefix && !timed_out {
+            return Vec::new();
+        }
+        let mut keys = Vec::new();
+        let rest = match mappings
+            .iter()
+            .filter(|mapping| mapping.lhs.len() <= self.pending.len() && is_same_keys(&mapping.lhs, &self.pending[..mapping.lhs.len()]))
+            .max_by_key(|mapping| mapping.lhs.len())
+        {
+            Some(mapping) => {
+                // like nmap j jzz, a right side that starts with the left side does not map that part again
+                let starts_with_lhs = mapping.rhs.len() >= mapping.lhs.len() && is_same_keys(&mapping.rhs[..mapping.lhs.len()], &mapping.lhs);
+                let literal = if starts_with_lhs { mapping.lhs.len() } else { 0 };
+                keys.extend(mapping.rhs.iter().enumerate().map(|(index, key)| (*key, mapping.recursive && index >= literal)));
+                mapping.lhs.len()
+            }
+            None => {
+                keys.push((self.pending[0], false));
+                1
+            }
+        };
+        // what follows runs after the mapping, maybe in another mode, so it is looked up again
+        keys.extend(self.pending.drain(..).skip(rest).map(|key| (key, true)));
+        keys
+    }
+
+    pub fn get_lines(&self, modes: &str, lhs: Option<&[Key]>) -> Vec<String> {
+        self.mappings
+            .iter()
+            .filter(|mapping| modes.contains(mapping.mode) && lhs.is_none_or(|lhs| is_same_keys(&mapping.lhs, lhs)))
+            .map(|mapping| {
+                format!(
+                    "{}  {:<12} {} {}",
+                    mapping.mode,
+                    format_keys(&mapping.lhs),
+                    if mapping.recursive { " " } else { "*" },
+                    format_keys(&mapping.rhs),
+                )
+            })
+            .collect()
+    }
+}
+
+// the map mode for the editor mode, normal maps only apply before an operator or prefix is typed
+pub fn get_map_mode(mode: char, prev_keys: &str) -> char {
+    match mode {
+        'n' if prev_keys.is_empty() => 'n',
+        'i' => 'i',
+        'v' | 'V' => 'v',
+        _ => '\0',
+    }
+}
+
+// shift is part of the character already, so A matches whether or not the terminal reports it
+fn is_same_key(a: Key, b: Key) -> bool {
+    let strip = |(code, modifiers): Key| match code {
+        KeyCode::Char(_) => (code, modifiers - KeyModifiers::SHIFT),
+        _ => (code, modifiers),
+    };
+    strip(a) == strip(b)
+}
+
+fn is_same_keys(a: &[Key], b: &[Key]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| is_same_key(*a, *b))
+}
+
+fn get_named_key(name: &str) -> Option<Key> {
+    let lower = name.to_lowercase();
+    if let Some((modifier, _)) = lower.split_once('-') {
+        let modifiers = match modifier {
+            "c" => KeyModifiers::CONTROL,
+            "a" | "m" => KeyModifiers::ALT,
+            "s" => KeyModifiers::SHIFT,
+            _ => return None,
+        };
+        // keep the case of the key itself, <C-a> and <A-A> differ
+        let key = &name[modifier.len() + 1..];
+        let mut chars = key.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(chr), None) => KeyCode::Char(chr),
+            _ => get_named_key(key)?.0,
+        };
+        return match (code, modifiers) {
+            (KeyCode::Tab, KeyModifiers::SHIFT) => Some((KeyCode::BackTab, KeyModifiers::SHIFT)),
+            (KeyCode::Char(chr), KeyModifiers::CONTROL) => Some((KeyCode::Char(chr.to_ascii_lowercase()), modifiers)),
+            _ => Some((code, modifiers)),
+        };
+    }
+    let code = match lower.as_str() {
+        "esc" => KeyCode::Esc,
+        "cr" | "enter" | "return" => KeyCode::Enter,
+        "tab" => KeyCode::Tab,
+        "bs" | "backspace" => KeyCode::Backspace,
+        "del" | "delete" => KeyCode::Delete,
+        "space" => KeyCode::Char(' '),
+        "lt" => KeyCode::Char('<'),
+        "bar" => KeyCode::Char('|'),
+        "bslash" => KeyCode::Char('\\'),
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        _ => match lower.strip_prefix('f').map(|number| number.parse::<u8>()) {
+            Some(Ok(number)) if (1..=12).contains(&number) => KeyCode::F(number),
+            _ => return None,
+        },
+    };
+    Some((code, KeyModifiers::NONE))
+}
+
+// vim key notation like <leader>w, <C-s> or jk; a < that starts no known name is just <
+pub fn parse_keys(text: &str, leader: &str) -> Result<Vec<Key>, String> {
+    let mut keys = Vec::new();
+    let mut rest = text;
+    while let Some(chr) = rest.chars().next() {
+        if chr == '<' {
+            if let Some((name, after)) = rest[1..].split_once('>') {
+                if name.eq_ignore_ascii_case("leader") {
+                    keys.extend(parse_keys(leader, "")?);
+                    rest = after;
+                    continue;
+                }
+                if let Some(key) = get_named_key(name) {
+                    keys.push(key);
+                    rest = after;
+                    continue;
+                }
+            }
+        }
+        keys.push((KeyCode::Char(chr), KeyModifiers::NONE));
+        rest = &rest[chr.len_utf8()..];
+    }
+    if keys.is_empty() {
+        return Err("No keys given".to_string());
+    }
+    Ok(keys)
+}
+
+pub fn format_keys(keys: &[Key]) -> String {
+    keys.iter()
+        .map(|&(code, modifiers)| {
+            let name = match code {
+                KeyCode::Char(' ') => "Space".to_string(),
+                KeyCode::Char('<') => "lt".to_string(),
+                KeyCode::Char(chr) if modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => chr.to_string(),
+                KeyCode::Char(chr) => return chr.to_string(),
+                KeyCode::Esc => "Esc".to_string(),
+                KeyCode::Enter => "CR".to_string(),
+                KeyCode::Tab => "Tab".to_string(),
+                KeyCode::BackTab => "S-Tab".to_string(),
+                KeyCode::Backspace => "BS".to_string(),
+                KeyCode::Delete => "Del".to_string(),
+                KeyCode::F(number) => format!("F{}", number),
+                code => format!("{:?}", code),
+            };
+            if modifiers.contains(KeyModifiers::CONTROL) {
+                format!("<C-{}>", name)
+            } else if modifiers.contains(KeyModifiers::ALT) {
+                format!("<A-{}>", name)
+            } else {
+                format!("<{}>", name)
+            }
+        })
+        .collect()
+}
+
+// :nmap lhs rhs, :nmap lhs to show one, :nmap to list them
+pub fn run_map_command(keymap: &mut Keymap, modes: &str, recursive: bool, arg: &str, leader: &str) -> Result<String, String> {
+    let (lhs, rhs) = match arg.split_once(char::is_whitespace) {
+        Some((lhs, rhs)) => (lhs, rhs.trim_start()),
+        None => (arg, ""),
+    };
+    if lhs.is_empty() || rhs.is_empty() {
+        let lhs = if lhs.is_empty() { None } else { Some(parse_keys(lhs, leader)?) };
+        let lines = keymap.get_lines(modes, lhs.as_deref());
+        if lines.is_empty() {
+            return Ok("No mapping found".to_string());
+        }
+        return Ok(lines.join("\n"));
+    }
+    let lhs = parse_keys(lhs, leader)?;
+    let rhs = parse_keys(rhs, leader)?;
+    for mode in modes.chars() {
+        keymap.map(mode, lhs.clone(), rhs.clone(), recursive);
+    }
+    Ok("".to_string())
+}
+
+pub fn run_unmap_command(keymap: &mut Keymap, modes: &str, arg: &str, leader: &str) -> Result<(), String> {
+    let lhs = parse_keys(arg.trim(), leader)?;
+    let mut found = false;
+    for mode in modes.chars() {
+        found |= keymap.unmap(mode, &lhs);
+    }
+    if found {
+        Ok(())
+    } else {
+        Err(format!("No such mapping: {}", arg.trim()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(text: &str) -> Vec<Key> {
+        parse_keys(text, "\\").unwrap()
+    }
+
+    fn feed_all(keymap: &mut Keymap, mode: char, text: &str) -> Vec<(Key, bool)> {
+        keys(text).into_iter().flat_map(|key| keymap.feed(mode, key)).collect()
+    }
+
+    #[test]
+    fn parses_key_notation() {
+        assert_eq!(keys("<leader>w"), [(KeyCode::Char('\\'), KeyModifiers::NONE), (KeyCode::Char('w'), KeyModifiers::NONE)]);
+        assert_eq!(keys("<C-S>"), [(KeyCode::Char('s'), KeyModifiers::CONTROL)]);
+        assert_eq!(keys("<S-Tab><esc>"), [(KeyCode::BackTab, KeyModifiers::SHIFT), (KeyCode::Esc, KeyModifiers::NONE)]);
+        assert_eq!(keys("<nope>").len(), 6);
+        assert!(parse_keys("", "").is_err());
+        assert_eq!(format_keys(&keys("<C-w>x<CR>")), "<C-w>x<CR>");
+    }
+
+    #[test]
+    fn waits_for_a_longer_mapping() {
+        let mut keymap = Keymap::new();
+        keymap.map('i', keys("jk"), keys("<Esc>"), true);
+        assert!(feed_all(&mut keymap, 'i', "j").is_empty());
+        assert_eq!(feed_all(&mut keymap, 'i', "k"), [((KeyCode::Esc, KeyModifiers::NONE), true)]);
+        assert!(keymap.pending.is_empty());
+        // another key after the j runs it as typed and looks the rest up again
+        assert_eq!(feed_all(&mut keymap, 'i', "jx"), [(keys("j")[0], false), (keys("x")[0], true)]);
+    }
+
+    #[test]
+    fn flushes_on_timeout() {
+        let mut keymap = Keymap::new();
+        keymap.map('n', keys("\\"), keys("dd"), true);
+        keymap.map('n', keys("\\w"), keys(":w<CR>"), false);
+        assert!(feed_all(&mut keymap, 'n', "\\").is_empty());
+        assert_eq!(keymap.flush('n'), [(keys("d")[0], true), (keys("d")[0], true)]);
+        assert!(keymap.flush('n').is_empty());
+    }
+
+    #[test]
+    fn only_maps_in_its_mode() {
+        let mut keymap = Keymap::new();
+        keymap.map('v', keys("x"), keys("d"), false);
+        assert_eq!(feed_all(&mut keymap, 'n', "x"), [(keys("x")[0], false)]);
+        assert_eq!(feed_all(&mut keymap, 'v', "x"), [(keys("d")[0], false)]);
+        assert_eq!(get_map_mode('n', "d"), '\0');
+    }
+
+    #[test]
+    fn does_not_remap_its_own_prefix() {
+        let mut keymap = Keymap::new();
+        keymap.map('n', keys("j"), keys("jzz"), true);
+        assert_eq!(feed_all(&mut keymap, 'n', "j"), [(keys("j")[0], false), (keys("z")[0], true), (keys("z")[0], true)]);
+    }
+
+    #[test]
+    fn maps_and_unmaps_from_commands() {
+        let mut keymap = Keymap::new();
+        assert_eq!(run_map_command(&mut keymap, "nv", false, "<leader>q :q<CR>", " "), Ok("".to_string()));
+        assert_eq!(keymap.mappings.len(), 2);
+        assert_eq!(run_map_command(&mut keymap, "n", true, "<Space>q", " ").unwrap(), "n  <Space>q     * :q<CR>");
+        assert!(run_unmap_command(&mut keymap, "v", "<leader>q", " ").is_ok());
+        assert!(run_unmap_command(&mut keymap, "v", "<leader>q", " ").is_err());
+        assert_eq!(keymap.mappings.len(), 1);
+    }
+}
@@ -0,0 +1,361 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+use crate::config;
+use crate::options::Value;
+use crate::text::{LineRope, TextBuffer};
+
+const BUILTIN_LANGUAGES: &str = include_str!("languages.toml");
+pub const LANGUAGES_FILE_NAME: &str = "languages.toml";
+const DEFAULT_INDENT_AFTER: &[&str] = &["(", "[", "{"];
+const DEFAULT_BRACKETS: &[(char, char)] = &[('(', ')'), ('[', ']'), ('{', '}')];
+pub const DEFAULT_STRINGS: &[&str] = &["\"", "'"];
+// lines at the start and end of a file searched for a vim: ft= modeline
+const MODELINE_LINES: usize = 5;
+
+static LANGUAGES: OnceLock<Vec<Language>> = OnceLock::new();
+
+pub struct Language {
+    pub name: String,
+    pub files: Vec<String>,
+    pub shebangs: Vec<String>,
+    pub line_comment: Option<String>,
+    pub block_comment: Option<(String, String)>,
+    pub indent: Option<usize>,
+    pub indent_after: Vec<String>,
+    pub brackets: Vec<(char, char)>,
+    pub keywords: Vec<String>,
+    pub types: Vec<String>,
+    pub constants: Vec<String>,
+    // open and close tokens of strings that end with the line, strings that span lines and ones without escapes
+    pub strings: Vec<(String, String)>,
+    pub multiline_strings: Vec<(String, String)>,
+    pub raw_strings: Vec<(String, String)>,
+    // a ' starts a character like 'a' or '\n' and is left alone otherwise, as in rust lifetimes
+    pub char_literals: bool,
+    pub nested_comments: bool,
+    // syntax tree node kinds for the languages with a built in grammar: what the function, class and block text
+    // objects and folds select, the lists whose children are arguments and what indents the lines inside it
+    pub function_nodes: Vec<String>,
+    pub class_nodes: Vec<String>,
+    pub block_nodes: Vec<String>,
+    pub argument_lists: Vec<String>,
+    pub indent_nodes: Vec<String>,
+}
+
+impl Language {
+    fn new(name: &str) -> Self {
+        Language {
+            name: name.to_string(),
+            files: Vec::new(),
+            shebangs: Vec::new(),
+            line_comment: None,
+            block_comment: None,
+            indent: None,
+            indent_after: DEFAULT_INDENT_AFTER.iter().map(|token| token.to_string()).collect(),
+            brackets: DEFAULT_BRACKETS.to_vec(),
+            keywords: Vec::new(),
+            types: Vec::new(),
+            constants: Vec::new(),
+            strings: DEFAULT_STRINGS.iter().map(|quote| (quote.to_string(), quote.to_string())).collect(),
+            multiline_strings: Vec::new(),
+            raw_strings: Vec::new(),
+            char_literals: false,
+            nested_comments: false,
+            function_nodes: Vec::new(),
+            class_nodes: Vec::new(),
+            block_nodes: Vec::new(),
+            argument_lists: Vec::new(),
+            indent_nodes: Vec::new(),
+        }
+    }
+
+    fn set(&mut self, key: &str, value: Value) -> Result<(), String> {
+        match (key, value) {
+            ("files", Value::List(files)) => self.files = files,
+            ("shebangs", Value::List(shebangs)) => self.shebangs = shebangs,
+            ("line_comment", Value::Str(token)) if !token.is_empty() => self.line_comment = Some(token),
+            ("block_comment", Value::List(tokens)) if tokens.len() == 2 && tokens.iter().all(|token| !token.is_empty()) => {
+                self.block_comment = Some((tokens[0].clone(), tokens[1].clone()));
+            }
+            ("indent", Value::Int(indent)) if (1..=16).contains(&indent) => self.indent = Some(indent as usize),
+            ("indent_after", Value::List(tokens)) => self.indent_after = tokens,
+            ("brackets", Value::List(pairs)) => {
+                self.brackets = Vec::new();
+                for pair in pairs {
+                    let chars: Vec<char> = pair.chars().collect();
+                    match chars[..] {
+                        [open, close] => self.brackets.push((open, close)),
+                        _ => return Err(format!("Bracket pairs are two characters, not {:?}", pair)),
+                    }
+                }
+            }
+            ("keywords", Value::List(words)) => self.keywords = words,
+            ("types", Value::List(words)) => self.types = words,
+            ("constants", Value::List(words)) => self.constants = words,
+            ("strings", Value::List(rules)) => self.strings = parse_string_rules(&rules)?,
+            ("multiline_strings", Value::List(rules)) => self.multiline_strings = parse_string_rules(&rules)?,
+            ("raw_strings", Value::List(rules)) => self.raw_strings = parse_string_rules(&rules)?,
+            ("function_nodes", Value::List(kinds)) => self.function_nodes = kinds,
+            ("class_nodes", Value::List(kinds)) => self.class_nodes = kinds,
+            ("block_nodes", Value::List(kinds)) => self.block_nodes = kinds,
+            ("argument_lists", Value::List(kinds)) => self.argument_lists = kinds,
+            ("indent_nodes", Value::List(kinds)) => self.indent_nodes = kinds,
+            ("char_literals", Value::Bool(value)) => self.char_literals = value,
+            ("nested_comments", Value::Bool(value)) => self.nested_comments = value,
+            (
+                "files" | "shebangs" | "indent_after" | "brackets" | "keywords" | "types" | "constants" | "strings"
+                | "multiline_strings" | "raw_strings" | "function_nodes" | "class_nodes" | "block_nodes" | "argument_lists"
+                | "indent_nodes",
+                _,
+            ) => return Err(format!("{} expects a list of strings", key)),
+            ("char_literals" | "nested_comments", _) => return Err(format!("{} expects true or false", key)),
+            ("line_comment", _) => return Err("line_comment expects a string".to_string()),
+            ("block_comment", _) => return Err("block_comment expects a list of the open and close tokens".to_string()),
+            ("indent", _) => return Err("indent expects a number from 1 to 16".to_string()),
+            (key, _) => return Err(format!("Unknown language key {}", key)),
+        }
+        Ok(())
+    }
+}
+
+// "\"" is a string quoted by the same token on both ends, "r#\" \"#" gives the open and close tokens
+fn parse_string_rules(rules: &[String]) -> Result<Vec<(String, String)>, String> {
+    rules
+        .iter()
+        .map(|rule| match rule.split_once(' ') {
+            Some((open, close)) if !open.is_empty() && !close.is_empty() => Ok((open.to_string(), close.to_string())),
+            None if !rule.is_empty() => Ok((rule.clone(), rule.clone())),
+            _ => Err(format!("Strings are a quote or an open and close token, not {:?}", rule)),
+        })
+        .collect()
+}
+
+// adds each [language] section to the list, changing only the given keys of a language already there;
+// returns the names of the sections and what was wrong with them
+fn add_languages(languages: &mut Vec<Language>, text: &str) -> (Vec<String>, Vec<(usize, String)>) {
+    let (entries, mut errors) = config::parse_config(text);
+    let mut names = Vec::new();
+    for entry in entries {
+        if entry.section.is_empty() {
+            errors.push((entry.line, format!("{} is outside a [language] section", entry.key)));
+            continue;
+        }
+        if !names.contains(&entry.section) {
+            names.push(entry.section.clone());
+        }
+        let index = match languages.iter().position(|language| language.name == entry.section) {
+            Some(index) => index,
+            None => {
+                languages.push(Language::new(&entry.section));
+                languages.len() - 1
+            }
+        };
+        if let Err(err) = languages[index].set(&entry.key, entry.value) {
+            errors.push((entry.line, err));
+        }
+    }
+    (names, errors)
+}
+
+fn get_builtin_languages() -> Vec<Language> {
+    let mut languages = Vec::new();
+    add_languages(&mut languages, BUILTIN_LANGUAGES);
+    languages
+}
+
+// the built in languages and the user's languages.toml; returns what was wrong with the file
+pub fn load_languages(clean: bool) -> Vec<String> {
+    let mut languages = get_builtin_languages();
+    let mut errors = Vec::new();
+    if let (false, Some(path)) = (clean, config::get_config_dir().map(|dir| dir.join(LANGUAGES_FILE_NAME))) {
+        match fs::read_to_string(&path) {
+            Ok(text) => {
+                let (names, file_errors) = add_languages(&mut languages, &text);
+                for (line, err) in file_errors {
+                    errors.push(format!("{}:{}: {}", path.display(), line, err));
+                }
+                // the user's languages go first so their globs win over the built in ones
+                languages.sort_by_key(|language| !names.contains(&language.name));
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+            Err(err) => errors.push(format!("{}: {}", path.display(), err)),
+        }
+    }
+    let _ = LANGUAGES.set(languages);
+    errors
+}
+
+pub fn get_languages() -> &'static [Language] {
+    LANGUAGES.get_or_init(get_builtin_languages)
+}
+
+pub fn get_language(name: &str) -> Option<&'static Language> {
+    get_languages().iter().find(|language| language.name == name)
+}
+
+// * matches any run of characters and ? any one
+pub fn matches_glob(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut star = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|chr| *chr == '*')
+}
+
+// the interpreter of a #! line, looking past env and its options
+fn get_shebang_program(line: &str) -> Option<&str> {
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+    let mut program = words.next()?.rsplit('/').next()?;
+    if program == "env" {
+        program = words.find(|word| !word.starts_with('-') && !word.contains('='))?;
+    }
+    Some(program)
+}
+
+// vim: set ft=python: or vim: filetype=sh in a comment near the start or end of the file
+fn get_modeline_name(line: &str) -> Option<&str> {
+    let (_, settings) = line.split_once("vim:").or_else(|| line.split_once("vi:"))?;
+    settings
+        .split(|c: char| c.is_whitespace() || c == ':')
+        .find_map(|setting| setting.strip_prefix("ft=").or_else(|| setting.strip_prefix("filetype=")))
+        .filter(|name| !name.is_empty())
+}
+
+// a modeline wins over the file name, which wins over the shebang
+pub fn detect(file_name: &str, file_data: &LineRope) -> Option<&'static Language> {
+    let languages = get_languages();
+    let len = file_data.len();
+    let modeline_lines = (0..len.min(MODELINE_LINES)).chain(len.saturating_sub(MODELINE_LINES).max(MODELINE_LINES)..len);
+    for y in modeline_lines {
+        if let Some(language) = get_modeline_name(&file_data[y]).and_then(get_language) {
+            return Some(language);
+        }
+    }
+    let base_name = Path::new(file_name).file_name().and_then(|name| name.to_str()).unwrap_or(file_name);
+    if let Some(language) = languages.iter().find(|language| language.files.iter().any(|glob| matches_glob(glob, base_name))) {
+        return Some(language);
+    }
+    let program = get_shebang_program(if len > 0 { &file_data[0] } else { "" })?;
+    languages.iter().find(|language| {
+        language.shebangs.iter().any(|shebang| {
+            // python matches python3 and python3.12
+            program.strip_prefix(shebang.as_str()).is_some_and(|version| version.chars().all(|c| c.is_ascii_digit() || c == '.'))
+        })
+    })
+}
+
+pub fn get_name(language: Option<&Language>) -> &str {
+    language.map_or("", |language| language.name.as_str())
+}
+
+pub fn get_indent_width(language: Option<&Language>, tabstop: usize) -> usize {
+    language.and_then(|language| language.indent).unwrap_or(tabstop)
+}
+
+// whether the line before the cursor ends in a token that indents the next line; words need a space before them
+pub fn is_indent_after(language: Option<&Language>, line: &str) -> bool {
+    let line = line.trim_end();
+    let ends_with = |token: &str| {
+        line.strip_suffix(token).is_some_and(|before| {
+            !token.chars().all(char::is_alphanumeric) || !before.ends_with(|c: char| c.is_alphanumeric() || c == '_')
+        })
+    };
+    match language {
+        Some(language) => language.indent_after.iter().any(|token| ends_with(token)),
+        None => DEFAULT_INDENT_AFTER.iter().any(|token| ends_with(token)),
+    }
+}
+
+// the closing bracket for a line that ends in an opening one
+pub fn get_closing_bracket(language: Option<&Language>, line: &str) -> Option<char> {
+    let last = line.trim_end().chars().last()?;
+    let brackets = language.map_or(DEFAULT_BRACKETS, |language| &language.brackets[..]);
+    brackets.iter().find(|(open, _)| *open == last).map(|(_, close)| *close)
+}
+
+// the line and block comment tokens, files of no known language get the most common one
+pub fn get_comment_tokens(language: Option<&Language>) -> (Option<&str>, Option<(&str, &str)>) {
+    match language {
+        Some(language) => (
+            language.line_comment.as_deref(),
+            language.block_comment.as_ref().map(|(open, close)| (open.as_str(), close.as_str())),
+        ),
+        None => (Some("#"), None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect_name(file_name: &str, lines: &[&str]) -> String {
+        let file_data = LineRope::from_lines(lines.iter().map(|line| line.to_string()).collect());
+        get_name(detect(file_name, &file_data)).to_string()
+    }
+
+    #[test]
+    fn matches_globs() {
+        assert!(matches_glob("*.rs", "main.rs"));
+        assert!(matches_glob("*.rs", ".rs"));
+        assert!(!matches_glob("*.rs", "main.rsx"));
+        assert!(matches_glob("Makefile", "Makefile"));
+        assert!(!matches_glob("Makefile", "Makefile.am"));
+        assert!(matches_glob("*.?s", "a.ts"));
+        assert!(matches_glob("a*b*c", "aXbYbZc"));
+        assert!(!matches_glob("a*b*c", "aXbYc!"));
+        assert!(matches_glob("*", ""));
+        assert!(!matches_glob("?", ""));
+    }
+
+    #[test]
+    fn parses_the_builtin_languages() {
+        let mut languages = Vec::new();
+        let (_, errors) = add_languages(&mut languages, BUILTIN_LANGUAGES);
+        assert_eq!(errors, Vec::new());
+        assert!(languages.iter().any(|language| language.name == "rust"));
+    }
+
+    #[test]
+    fn detects_by_file_name() {
+        assert_eq!(detect_name("src/main.rs", &[]), "rust");
+        assert_eq!(detect_name("dir/Rakefile", &["task :x"]), "ruby");
+        assert_eq!(detect_name("notes.unknown", &["text"]), "");
+    }
+
+    #[test]
+    fn detects_by_shebang() {
+        assert_eq!(detect_name("script", &["#!/usr/bin/env python3.12", "print(1)"]), "python");
+        assert_eq!(detect_name("script", &["#!/usr/bin/env -S node --flag"]), "javascript");
+        assert_eq!(detect_name("script", &["#!/bin/bash"]), "sh");
+        assert_eq!(detect_name("script", &["#!/usr/bin/pythonista"]), "");
+        assert_eq!(detect_name("main.rs", &["#!/bin/sh"]), "rust");
+    }
+
+    #[test]
+    fn detects_by_modeline() {
+        assert_eq!(detect_name("main.rs", &["# vim: set ft=python:"]), "python");
+        let mut lines = vec!["x"; 20];
+        lines.push("# vim: filetype=sh");
+        assert_eq!(detect_name("file", &lines), "sh");
+        lines.insert(10, "# vim: ft=ruby");
+        assert_eq!(detect_name("file", &lines), "sh");
+        assert_eq!(detect_name("main.rs", &["// vim: ft=nosuchlanguage"]), "rust");
+    }
+}
@@ -2,111 +2,294 @@ use crossterm::event::{Event, KeyCode, KeyEvent, KeyModifiers};
 use crossterm::execute;
 use crossterm::terminal::{enable_raw_mode, EnterAlternateScreen};
 use crossterm::style::{Color};
-use std::io::{stdout};
-use crossterm::terminal::size;
+use std::collections::VecDeque;
+use std::io::{stdout, IsTerminal, Write};
 use std::panic;
+use std::process;
+use std::time::Duration;
+use text::TextBuffer;
+mod buffer;
+mod cli;
+mod command;
+mod config;
 mod diffhist;
+mod error;
+mod explorer;
+mod finder;
 mod helper;
+mod keymap;
+mod language;
+mod options;
+mod quickfix;
+mod statusline;
+mod swap;
+mod syntax;
+mod tab;
+mod text;
+mod theme;
+mod tree;
+mod window;
 
 fn send_command(
     code: KeyCode,
     modifiers: KeyModifiers,
-    file_data: &mut Vec<String>,
-    file_name: &String,
-    cursor_x: &mut usize,
-    cursor_y: &mut usize,
+    buffers: &mut Vec<buffer::Buffer>,
+    tabs: &mut tab::Tabs,
+    finder: &mut finder::Finder,
+    quickfix: &mut quickfix::Quickfix,
+    explorer: &mut explorer::Explorer,
+    options: &mut options::Options,
+    keymap: &mut keymap::Keymap,
     visual_x: &mut usize,
     visual_y: &mut usize,
     mode: &mut char,
     prev_keys: &mut String,
     last_command: &mut Vec<(KeyCode, KeyModifiers)>,
     recording: &mut bool,
-    search_string: &mut String,
-    searching: &mut bool,
     macro_command: &mut Vec<(KeyCode, KeyModifiers)>,
     macro_recording: &mut bool,
-    diff_history: &mut diffhist::DiffHistory,
-    window_line_x: &mut usize,
-    window_line_y: &mut usize,
-    pos: &mut (usize, usize),
+    command_string: &mut String,
+    quit: &mut bool,
+    message_history: &mut Vec<String>,
+    message: &mut String,
     ) {
-    if *mode == 'n' {
-        *pos = (*cursor_x, *cursor_y);
-        *searching = false;
-        if prev_keys == "r" && !modifiers.contains(KeyModifiers::CONTROL) {
-            if *cursor_x < file_data[*cursor_y].len() {
-                if let KeyCode::Char(c) = code {
-                    file_data[*cursor_y].remove(*cursor_x);
-                    file_data[*cursor_y].insert(*cursor_x, c);
-                    helper::log_command(code, modifiers, last_command, *recording);
-                    helper::save_to_file(file_data, file_name, diff_history, *pos);
-                }
+    if *mode == ':' {
+        if code == KeyCode::Esc {
+            *mode = 'n';
+        } else if code == KeyCode::Enter {
+            *mode = 'n';
+            let context = command::CommandContext { buffers, tabs, quickfix, explorer, options, keymap, quit };
+            command::run_command(command_string, context, message_history, message);
+        } else if code == KeyCode::Backspace {
+            if command_string.pop().is_none() {
+                *mode = 'n';
             }
+        } else if let KeyCode::Char(c) = code {
+            command_string.push(c);
+        }
+        return;
+    }
+    if *mode == 'f' {
+        finder::send_finder_command(code, modifiers, finder, buffers, tabs, mode, message);
+        return;
+    }
+    // a read only buffer refuses a change before it runs, so nothing reaches the clipboard either
+    if helper::is_edit_key(*mode, prev_keys, code, modifiers) {
+        if let Err(err) = buffers[tabs.current().current().buffer].check_editable() {
+            *message = err.to_string();
             *prev_keys = "".to_string();
-        } else if code == KeyCode::Char('z') {
-            (*window_line_x, *window_line_y) = helper::center_screen(*cursor_y);
+            if *mode == 'i' {
+                *mode = 'n';
+            }
+            return;
+        }
+    }
+    if *mode == 'n' && *prev_keys == "^w" {
+        window::window_command(code, tabs.current(), message);
+        *prev_keys = "".to_string();
+        return;
+    }
+    if *mode == 'n' && *prev_keys == "g" && (code == KeyCode::Char('t') || code == KeyCode::Char('T')) {
+        if code == KeyCode::Char('t') {
+            tabs.next();
+        } else {
+            tabs.prev();
+        }
+        *prev_keys = "".to_string();
+        return;
+    }
+    if *mode == 'n' && (prev_keys != "r" || modifiers.contains(KeyModifiers::CONTROL)) {
+        if code == KeyCode::Char('w') && modifiers.contains(KeyModifiers::CONTROL) {
+            *prev_keys = "^w".to_string();
+            return;
+        } else if code == KeyCode::Char('p') && modifiers.contains(KeyModifiers::CONTROL) {
+            *mode = 'f';
+            *prev_keys = "".to_string();
+            finder.open(".");
+            return;
+        } else if code == KeyCode::Enter && buffers[tabs.current().current().buffer].file_name == quickfix::QUICKFIX_NAME {
+            let entry = tabs.current().current().cursor_y;
+            quickfix::jump_to_entry(quickfix, entry, buffers, tabs.current(), message);
+            return;
+        } else if code == KeyCode::Char('s') && modifiers.contains(KeyModifiers::CONTROL) {
+            let buffer = tabs.current().current().buffer;
+            error::report(buffers[buffer].write(), message);
+            return;
+        } else if code == KeyCode::Char('e') && modifiers.contains(KeyModifiers::CONTROL) {
+            explorer::toggle_window(explorer, buffers, tabs.current());
+            *prev_keys = "".to_string();
+            return;
+        } else if prev_keys.is_empty()
+            && !modifiers.contains(KeyModifiers::CONTROL)
+            && buffers[tabs.current().current().buffer].file_name == explorer::EXPLORER_NAME
+            && explorer::send_explorer_command(code, explorer, buffers, tabs.current(), mode, command_string, message) {
+            return;
+        } else if code == KeyCode::Char(':') {
+            *mode = ':';
+            *prev_keys = "".to_string();
+            *command_string = "".to_string();
+            return;
         } else if code == KeyCode::Char(',') {
             *recording = false;
             for (macro_code, macro_modifiers) in macro_command.iter() {
                 send_command(
                     *macro_code,
                     *macro_modifiers,
-                    file_data,
-                    file_name,
-                    cursor_x,
-                    cursor_y,
+                    buffers,
+                    tabs,
+                    finder,
+                    quickfix,
+                    explorer,
+                    options,
+                    keymap,
                     visual_x,
                     visual_y,
                     mode,
                     prev_keys,
                     last_command,
                     recording,
-                    search_string,
-                    searching,
                     &mut Vec::new(),
                     macro_recording,
-                    diff_history,
-                    window_line_x,
-                    window_line_y,
-                    pos,
+                    command_string,
+                    quit,
+                    message_history,
+                    message,
                 );
             }
             *recording = true;
+            return;
         } else if code == KeyCode::Char('.') {
             *recording = false;
             for (last_code, last_modifiers) in last_command.iter() {
                 send_command(
                     *last_code,
                     *last_modifiers,
-                    file_data,
-                    file_name,
-                    cursor_x,
-                    cursor_y,
+                    buffers,
+                    tabs,
+                    finder,
+                    quickfix,
+                    explorer,
+                    options,
+                    keymap,
                     visual_x,
                     visual_y,
                     mode,
                     prev_keys,
                     &mut Vec::new(),
                     recording,
-                    search_string,
-                    searching,
                     macro_command,
                     macro_recording,
-                    diff_history,
-                    window_line_x,
-                    window_line_y,
-                    pos,
+                    command_string,
+                    quit,
+                    message_history,
+                    message,
                 );
             }
             *recording = true;
+            return;
+        }
+    }
+    let window::Window {
+        buffer,
+        cursor_x,
+        cursor_y,
+        window_line_x,
+        window_line_y,
+        height,
+        ..
+    } = tabs.current().current();
+    let buffer::Buffer {
+        file_data,
+        search_string,
+        searching,
+        diff_history,
+        modified,
+        saved_data,
+        scratch,
+        last_visual,
+        language,
+        tree,
+        ..
+    } = &mut buffers[*buffer];
+    let language = *language;
+    let indent_width = language::get_indent_width(language, options.tabstop);
+    if *mode == 'n' {
+        *searching = false;
+        if prev_keys == "r" && !modifiers.contains(KeyModifiers::CONTROL) {
+            if *cursor_x < file_data[*cursor_y].len() {
+                if let KeyCode::Char(c) = code {
+                    file_data[*cursor_y].remove(*cursor_x);
+                    file_data[*cursor_y].insert(*cursor_x, c);
+                    helper::log_command(code, modifiers, last_command, *recording);
+                }
+            }
+            *prev_keys = "".to_string();
+        } else if prev_keys.starts_with('!') {
+            // ! takes a line motion and opens the command line with its range, like !ip becomes :4,9!
+            if let KeyCode::Char(c) = code {
+                prev_keys.push(c);
+            }
+            let motion = prev_keys[1..].to_string();
+            if let Some((start, end)) = helper::get_motion_lines(file_data, *cursor_y, &motion, "!") {
+                *mode = ':';
+                *command_string = format!("{},{}!", start + 1, end + 1);
+                *prev_keys = "".to_string();
+            } else if !(motion == "g" || motion == "i" || motion == "a") {
+                *prev_keys = "".to_string();
+            }
+        } else if prev_keys.is_empty() && code == KeyCode::Char('!') {
+            *prev_keys = "!".to_string();
+        } else if prev_keys.starts_with("gc") {
+            // gc takes a line motion like gcip or gcj, gcc is the current line
+            if let KeyCode::Char(c) = code {
+                helper::log_command(code, modifiers, last_command, *recording);
+                prev_keys.push(c);
+            }
+            let motion = prev_keys[2..].to_string();
+            if let Some((start, end)) = helper::get_motion_lines(file_data, *cursor_y, &motion, "c") {
+                match language::get_comment_tokens(language) {
+                    (None, None) => *message = format!("No comments in {}", language::get_name(language)),
+                    (line_comment, block_comment) => helper::toggle_comments(file_data, line_comment, block_comment, start, end),
+                }
+                *cursor_y = start;
+                *cursor_x = helper::count_leading_spaces(&file_data[*cursor_y]);
+                *prev_keys = "".to_string();
+            } else if !(motion == "g" || motion == "i" || motion == "a") {
+                *prev_keys = "".to_string();
+            }
+        } else if *prev_keys == "z" {
+            // z on its own still centers the screen, once timeoutlen passes or with zz;
+            // the rest fold the functions, classes and blocks of the syntax tree
+            tree.update(language, file_data);
+            match code {
+                KeyCode::Char('z') => (*window_line_x, *window_line_y) = helper::center_screen(*cursor_y, *height),
+                KeyCode::Char('c') | KeyCode::Char('a') if code == KeyCode::Char('c') || tree.get_closed_fold(*cursor_y).is_none() => {
+                    match tree.close_fold(file_data, *cursor_y) {
+                        Ok(start) => *cursor_y = start,
+                        Err(err) => *message = err,
+                    }
+                }
+                KeyCode::Char('o') | KeyCode::Char('a') => match tree.open_fold(*cursor_y) {
+                    Ok(start) => *cursor_y = start,
+                    Err(err) => *message = err,
+                },
+                KeyCode::Char('R') => tree.folds.clear(),
+                KeyCode::Char('M') => {
+                    tree.folds = tree.get_all_fold_ranges();
+                    *cursor_y = tree.get_closed_fold(*cursor_y).map_or(*cursor_y, |(start, _)| start);
+                }
+                _ => (),
+            }
+            *prev_keys = "".to_string();
+        } else if prev_keys.is_empty() && code == KeyCode::Char('z') {
+            *prev_keys = "z".to_string();
         } else if code == KeyCode::Char('q') {
             if !*macro_recording {
                 macro_command.clear();
             }
             *macro_recording = !*macro_recording;
         } else if code == KeyCode::Char('d') && modifiers.contains(KeyModifiers::CONTROL) {
-            let terminal_size = size().unwrap();
-            let term_height = terminal_size.1 as usize;
+            let term_height = *height;
             let mut i = 0;
             while i < term_height {
                 *cursor_y = helper::down(&file_data, *cursor_y);
@@ -116,8 +299,7 @@ fn send_command(
                 i += 2;
             }
         } else if code == KeyCode::Char('u') && modifiers.contains(KeyModifiers::CONTROL) {
-            let terminal_size = size().unwrap();
-            let term_height = terminal_size.1 as usize;
+            let term_height = *height;
             let mut i = 0;
             while i < term_height {
                 *cursor_y = helper::up(*cursor_y);
@@ -126,21 +308,27 @@ fn send_command(
                 }
                 i += 2;
             }
-        } else if *prev_keys == "y" && code == KeyCode::Char('i') {
-            *prev_keys = "yi".to_string();
+        } else if *prev_keys == "y" && (code == KeyCode::Char('i') || code == KeyCode::Char('a')) {
+            if let KeyCode::Char(c) = code {
+                prev_keys.push(c);
+            }
         } else if *prev_keys == "yi" && code == KeyCode::Char('w') {
             *cursor_x = helper::reset_cursor_end(&file_data, *cursor_x, *cursor_y);
             match helper::get_in_word(&file_data[*cursor_y], *cursor_x) {
                 Some((begin, end)) => {
                     let new_end = helper::prevent_cursor_end(&file_data, end, *cursor_y);
-                    (*cursor_x, *cursor_y) = helper::copy_in_visual(file_data, begin, *cursor_y, new_end, *cursor_y, 'v');
+                    if let Some(cursor) = error::report(helper::copy_in_visual(file_data, begin, *cursor_y, new_end, *cursor_y, 'v', &options.clipboard), message) {
+                        (*cursor_x, *cursor_y) = cursor;
+                    }
                 }
                 None => ()
             };
             *prev_keys = "".to_string();
-        } else if *prev_keys == "c" && code == KeyCode::Char('i') {
+        } else if *prev_keys == "c" && (code == KeyCode::Char('i') || code == KeyCode::Char('a')) {
             helper::log_command(code, modifiers, last_command, *recording);
-            *prev_keys = "ci".to_string();
+            if let KeyCode::Char(c) = code {
+                prev_keys.push(c);
+            }
         } else if *prev_keys == "ci" && code == KeyCode::Char('w') {
             helper::log_command(code, modifiers, last_command, *recording);
             *cursor_x = helper::reset_cursor_end(&file_data, *cursor_x, *cursor_y);
@@ -153,21 +341,57 @@ fn send_command(
             };
             *mode = 'i';
             *prev_keys = "".to_string();
-        } else if *prev_keys == "d" && code == KeyCode::Char('i') {
+        } else if *prev_keys == "d" && (code == KeyCode::Char('i') || code == KeyCode::Char('a')) {
             helper::log_command(code, modifiers, last_command, *recording);
-            *prev_keys = "di".to_string();
+            if let KeyCode::Char(c) = code {
+                prev_keys.push(c);
+            }
         } else if *prev_keys == "di" && code == KeyCode::Char('w') {
             helper::log_command(code, modifiers, last_command, *recording);
             *cursor_x = helper::reset_cursor_end(&file_data, *cursor_x, *cursor_y);
             match helper::get_in_word(&file_data[*cursor_y], *cursor_x) {
                 Some((begin, end)) => {
                     let new_end = helper::prevent_cursor_end(&file_data, end, *cursor_y);
-                    helper::copy_in_visual(file_data, begin, *cursor_y, new_end, *cursor_y, 'v');
+                    error::report(helper::copy_in_visual(file_data, begin, *cursor_y, new_end, *cursor_y, 'v', &options.clipboard), message);
                     (*cursor_x, *cursor_y) = helper::delete_in_visual(file_data, begin, *cursor_y, new_end, *cursor_y, 'v');
                 }
                 None => ()
             };
-            helper::save_to_file(file_data, file_name, diff_history, *pos);
+            *prev_keys = "".to_string();
+        } else if matches!(prev_keys.as_str(), "yi" | "ya" | "ci" | "ca" | "di" | "da") {
+            // f function, c class, a argument and B block come from the syntax tree
+            let operator = prev_keys.remove(0);
+            if operator != 'y' {
+                helper::log_command(code, modifiers, last_command, *recording);
+            }
+            tree.update(language, file_data);
+            let object = match code {
+                KeyCode::Char(object) => tree.get_text_object(file_data, *cursor_x, *cursor_y, *prev_keys == "i", object),
+                _ => Err("".to_string()),
+            };
+            match object {
+                Ok(((begin_x, begin_y), (end_x, end_y))) => {
+                    // an object that starts and ends its lines is taken as whole lines
+                    let whole_lines = begin_x <= helper::count_leading_spaces(&file_data[begin_y]) && end_x + 1 >= file_data[end_y].len();
+                    let object_mode = if whole_lines { 'V' } else { 'v' };
+                    if operator != 'c' || whole_lines {
+                        error::report(helper::copy_in_visual(file_data, begin_x, begin_y, end_x, end_y, object_mode, &options.clipboard), message);
+                    }
+                    if operator == 'c' && whole_lines {
+                        helper::delete_in_visual_and_insert(file_data, begin_y, end_y);
+                        (*cursor_x, *cursor_y) = (0, begin_y);
+                    } else if operator == 'y' {
+                        (*cursor_x, *cursor_y) = (begin_x, begin_y);
+                    } else {
+                        (*cursor_x, *cursor_y) = helper::delete_in_visual(file_data, begin_x, begin_y, end_x, end_y, object_mode);
+                        *cursor_y = helper::reset_cursor_end_file(file_data.len(), *cursor_y);
+                    }
+                    if operator == 'c' {
+                        *mode = 'i';
+                    }
+                }
+                Err(err) => *message = err,
+            }
             *prev_keys = "".to_string();
         } else if code == KeyCode::Char('{') {
             *cursor_y = helper::get_prev_empty_line(&file_data, *cursor_y);
@@ -179,11 +403,12 @@ fn send_command(
         } else if code == KeyCode::Char('l') {
             *cursor_x = helper::right(&file_data, *cursor_x, *cursor_y);
         } else if code == KeyCode::Char('j') {
+            // a closed fold counts as one line
+            *cursor_y = tree.get_closed_fold(*cursor_y).map_or(*cursor_y, |(_, end)| end);
             *cursor_y = helper::down(&file_data, *cursor_y);
         } else if code == KeyCode::Char('k') {
             *cursor_y = helper::up(*cursor_y);
-        } else if code == KeyCode::Char('s') && modifiers.contains(KeyModifiers::CONTROL) {
-            helper::save_to_file(file_data, file_name, diff_history, *pos);
+            *cursor_y = tree.get_closed_fold(*cursor_y).map_or(*cursor_y, |(start, _)| start);
         } else if code == KeyCode::Char('$') {
             *cursor_x = helper::set_cursor_end(&file_data, *cursor_y);
             *cursor_x = helper::left(*cursor_x);
@@ -221,22 +446,25 @@ fn send_command(
         } else if code == KeyCode::Char('>') {
             last_command.clear();
             helper::log_command(code, modifiers, last_command, *recording);
-            file_data[*cursor_y] = helper::increase_indent(file_data[*cursor_y].clone());
+            file_data[*cursor_y] = helper::increase_indent(file_data[*cursor_y].clone(), indent_width);
             *cursor_x = helper::count_leading_spaces(&file_data[*cursor_y]);
-            helper::save_to_file(file_data, file_name, diff_history, *pos);
         } else if code == KeyCode::Char('<') {
             last_command.clear();
             helper::log_command(code, modifiers, last_command, *recording);
-            file_data[*cursor_y] = helper::reduce_indent(file_data[*cursor_y].clone());
+            file_data[*cursor_y] = helper::reduce_indent(file_data[*cursor_y].clone(), indent_width);
             *cursor_x = helper::count_leading_spaces(&file_data[*cursor_y]);
-            helper::save_to_file(file_data, file_name, diff_history, *pos);
         } else if code == KeyCode::Char('o') {
             last_command.clear();
             helper::log_command(code, modifiers, last_command, *recording);
-            let mut indent_level = helper::count_leading_spaces(&file_data[*cursor_y]);
-            if file_data[*cursor_y].ends_with('(') || file_data[*cursor_y].ends_with('{') {
-                indent_level += 4;
-            }
+            // past an opening bracket or colon indent once more, otherwise as deep as the syntax tree nests the line
+            tree.update(language, file_data);
+            let line_len = file_data[*cursor_y].len();
+            let indent_level = if language::is_indent_after(language, &file_data[*cursor_y]) {
+                helper::count_leading_spaces(&file_data[*cursor_y]) + indent_width
+            } else {
+                tree.get_indent(file_data, line_len, *cursor_y, indent_width)
+                    .unwrap_or_else(|| helper::count_leading_spaces(&file_data[*cursor_y]))
+            };
             file_data.insert(*cursor_y + 1, " ".repeat(indent_level).to_string());
             *cursor_x = indent_level;
             *cursor_y = helper::down(&file_data, *cursor_y);
@@ -244,10 +472,13 @@ fn send_command(
         } else if code == KeyCode::Char('O') {
             last_command.clear();
             helper::log_command(code, modifiers, last_command, *recording);
-            let mut indent_level = helper::count_leading_spaces(&file_data[*cursor_y]);
-            if file_data[*cursor_y].ends_with('(') || file_data[*cursor_y].ends_with('{') {
-                indent_level += 4;
-            }
+            tree.update(language, file_data);
+            let first_char = helper::count_leading_spaces(&file_data[*cursor_y]);
+            let indent_level = match tree.get_indent(file_data, first_char, *cursor_y, indent_width) {
+                Some(indent_level) => indent_level,
+                None if language::is_indent_after(language, &file_data[*cursor_y]) => first_char + indent_width,
+                None => first_char,
+            };
             *cursor_x = indent_level;
             file_data.insert(*cursor_y, " ".repeat(indent_level).to_string());
             *mode = 'i';
@@ -270,14 +501,12 @@ fn send_command(
         } else if code == KeyCode::Char('P') {
             last_command.clear();
             helper::log_command(code, modifiers, last_command, *recording);
-            helper::paste_before(file_data, *cursor_x, *cursor_y);
-            helper::save_to_file(file_data, file_name, diff_history, *pos);
+            error::report(helper::paste_before(file_data, *cursor_x, *cursor_y, &options.clipboard), message);
         } else if code == KeyCode::Char('p') {
             last_command.clear();
             helper::log_command(code, modifiers, last_command, *recording);
             *cursor_x = helper::prevent_cursor_end(&file_data, *cursor_x, *cursor_y);
-            helper::paste_after(file_data, *cursor_x, *cursor_y);
-            helper::save_to_file(file_data, file_name, diff_history, *pos);
+            error::report(helper::paste_after(file_data, *cursor_x, *cursor_y, &options.clipboard), message);
         } else if code == KeyCode::Char('s') {
             last_command.clear();
             helper::log_command(code, modifiers, last_command, *recording);
@@ -288,44 +517,35 @@ fn send_command(
             helper::log_command(code, modifiers, last_command, *recording);
             *cursor_x = helper::reset_cursor_end(&file_data, *cursor_x, *cursor_y);
             if *cursor_x < file_data[*cursor_y].len() {
-                helper::copy_to_clipboard(&file_data[*cursor_y][*cursor_x..*cursor_x + 1]).expect("Failed to copy to clipboard");
+                error::report(helper::copy_to_clipboard(&file_data[*cursor_y][*cursor_x..*cursor_x + 1], &options.clipboard), message);
                 file_data[*cursor_y].remove(*cursor_x);
-                helper::save_to_file(file_data, file_name, diff_history, *pos);
             }
             *cursor_x = helper::reset_cursor_end(&file_data, *cursor_x, *cursor_y);
         } else if *prev_keys == "g" && code == KeyCode::Char('c') {
             helper::log_command(code, modifiers, last_command, *recording);
-            let comment_string = match helper::get_comment_string(file_name) {
-                Some(chr) => chr,
-                None => "#",
-            };
-            file_data[*cursor_y] = helper::toggle_comment(file_data[*cursor_y].clone(), comment_string);
-            *cursor_x = helper::count_leading_spaces(&file_data[*cursor_y]);
-            *prev_keys = "".to_string();
-            helper::save_to_file(file_data, file_name, diff_history, *pos);
+            *prev_keys = "gc".to_string();
         } else if *prev_keys == "c" && code == KeyCode::Char('c') {
             helper::log_command(code, modifiers, last_command, *recording);
-            helper::copy_in_visual(file_data, *cursor_x, *cursor_y, *cursor_x, *cursor_y, 'V');
+            error::report(helper::copy_in_visual(file_data, *cursor_x, *cursor_y, *cursor_x, *cursor_y, 'V', &options.clipboard), message);
             helper::delete_in_visual_and_insert(file_data, *cursor_y, *cursor_y);
             *cursor_x = 0; // TODO make indent level
             *cursor_y = helper::reset_cursor_end_file(file_data.len(), *cursor_y);
             *mode = 'i';
             *prev_keys = "".to_string();
         } else if *prev_keys == "y" && code == KeyCode::Char('y') {
-            helper::copy_in_visual(file_data, *cursor_x, *cursor_y, *cursor_x, *cursor_y, 'V');
+            error::report(helper::copy_in_visual(file_data, *cursor_x, *cursor_y, *cursor_x, *cursor_y, 'V', &options.clipboard), message);
             *prev_keys = "".to_string();
         } else if *prev_keys == "d" && code == KeyCode::Char('d') {
             helper::log_command(code, modifiers, last_command, *recording);
-            helper::copy_in_visual(file_data, *cursor_x, *cursor_y, *cursor_x, *cursor_y, 'V');
+            error::report(helper::copy_in_visual(file_data, *cursor_x, *cursor_y, *cursor_x, *cursor_y, 'V', &options.clipboard), message);
             helper::delete_in_visual(file_data, *cursor_x, *cursor_y, *cursor_x, *cursor_y, 'V');
             *cursor_y = helper::reset_cursor_end_file(file_data.len(), *cursor_y);
             *prev_keys = "".to_string();
-            helper::save_to_file(file_data, file_name, diff_history, *pos);
         } else if *prev_keys == "" && code == KeyCode::Char('g') {
             last_command.clear();
             helper::log_command(code, modifiers, last_command, *recording);
             *prev_keys = "g".to_string();
-        } else if *prev_keys == "" && code == KeyCode::Char('r') {
+        } else if prev_keys.is_empty() && code == KeyCode::Char('r') && !modifiers.contains(KeyModifiers::CONTROL) {
             last_command.clear();
             helper::log_command(code, modifiers, last_command, *recording);
             *prev_keys = "r".to_string();
@@ -353,7 +573,7 @@ fn send_command(
                 Some((x, y)) => (x, y),
                 None => (*cursor_x, *cursor_y)
             };
-            (*window_line_x, *window_line_y) = helper::center_screen(*cursor_y);
+            (*window_line_x, *window_line_y) = helper::center_screen(*cursor_y, *height);
             *searching = true;
         } else if code == KeyCode::Char('n') {
             *cursor_x = helper::reset_cursor_end(&file_data, *cursor_x, *cursor_y);
@@ -361,28 +581,29 @@ fn send_command(
                 Some((x, y)) => (x, y),
                 None => (*cursor_x, *cursor_y)
             };
-            (*window_line_x, *window_line_y) = helper::center_screen(*cursor_y);
+            (*window_line_x, *window_line_y) = helper::center_screen(*cursor_y, *height);
             *searching = true;
         } else if code == KeyCode::Char('u') {
-            match diff_history.undo() {
-                Some((prev_state, (x, y))) => { *file_data = prev_state; *cursor_x = x; *cursor_y = y },
-                None => ()
+            if let Some((prev_state, (x, y))) = diff_history.undo() {
+                *file_data = prev_state;
+                (*cursor_x, *cursor_y) = (x, y);
+                if file_data.len() == 0 {
+                    file_data.insert(0, "".to_string());
+                }
+                // back at the saved text the buffer is clean again
+                *modified = !*scratch && *file_data != *saved_data;
             }
             *cursor_y = helper::reset_cursor_end_file(file_data.len(), *cursor_y);
-            if file_data.len() == 0 {
-                file_data.insert(0, "".to_string());
-            }
-            helper::save_to_file_no_snapshot(file_data, file_name);
         } else if code == KeyCode::Char('r') && modifiers.contains(KeyModifiers::CONTROL) {
-            match diff_history.redo() {
-                Some((next_state, (x, y))) => { *file_data = next_state; *cursor_x = x; *cursor_y = y },
-                None => ()
+            if let Some((next_state, (x, y))) = diff_history.redo() {
+                *file_data = next_state;
+                (*cursor_x, *cursor_y) = (x, y);
+                if file_data.len() == 0 {
+                    file_data.insert(0, "".to_string());
+                }
+                *modified = !*scratch && *file_data != *saved_data;
             }
             *cursor_y = helper::reset_cursor_end_file(file_data.len(), *cursor_y);
-            if file_data.len() == 0 {
-                file_data.insert(0, "".to_string());
-            }
-            helper::save_to_file_no_snapshot(file_data, file_name);
         } else if code == KeyCode::Esc {
             *prev_keys = "".to_string();
         }
@@ -400,35 +621,37 @@ fn send_command(
             Some((x, y)) => (x, y),
             None => (*cursor_x, *cursor_y)
         };
-        (*window_line_x, *window_line_y) = helper::center_screen(*cursor_y);
+        (*window_line_x, *window_line_y) = helper::center_screen(*cursor_y, *height);
         helper::log_command(code, modifiers, last_command, *recording);
     } else if *mode == 'i' {
         if code == KeyCode::Esc {
             *mode = 'n';
             *cursor_x = helper::left(*cursor_x);
-            helper::save_to_file(file_data, file_name, diff_history, *pos);
         } else if code == KeyCode::BackTab {
-            file_data[*cursor_y] = helper::reduce_indent(file_data[*cursor_y].clone());
-            if *cursor_x >= 4 {
-                *cursor_x -= 4;
+            file_data[*cursor_y] = helper::reduce_indent(file_data[*cursor_y].clone(), indent_width);
+            if *cursor_x >= indent_width {
+                *cursor_x -= indent_width;
             } else {
                 *cursor_x = 0;
             }
         } else if code == KeyCode::Tab {
-            file_data[*cursor_y] = helper::increase_indent(file_data[*cursor_y].clone());
-            *cursor_x += 4;
+            file_data[*cursor_y] = helper::increase_indent(file_data[*cursor_y].clone(), indent_width);
+            *cursor_x += indent_width;
         } else if code == KeyCode::Enter {
+            tree.update(language, file_data);
             let mut indent_level = helper::count_leading_spaces(&file_data[*cursor_y]);
-            if file_data[*cursor_y][..*cursor_x].ends_with('(') || file_data[*cursor_y][..*cursor_x].ends_with('{') {
-                indent_level += 4;
+            let before_cursor = &file_data[*cursor_y][..*cursor_x];
+            let closing_bracket = language::get_closing_bracket(language, before_cursor);
+            if language::is_indent_after(language, before_cursor) {
+                indent_level += indent_width;
+                // an opening bracket at the end of the line gets its closing one on the line after
+                if let Some(closing_bracket) = closing_bracket {
+                    file_data.insert(*cursor_y + 1, " ".repeat(indent_level - indent_width) + &closing_bracket.to_string());
+                }
+            } else if let Some(tree_indent) = tree.get_indent(file_data, *cursor_x, *cursor_y, indent_width) {
+                indent_level = tree_indent;
             }
             let substring = " ".repeat(indent_level) + &file_data[*cursor_y][*cursor_x..];
-            if file_data[*cursor_y][..*cursor_x].ends_with('(') {
-                file_data.insert(*cursor_y + 1, " ".repeat(indent_level - 4) + ")");
-            }
-            if file_data[*cursor_y][..*cursor_x].ends_with('{') {
-                file_data.insert(*cursor_y + 1, " ".repeat(indent_level - 4) + "}");
-            }
             file_data.insert(*cursor_y + 1, substring.to_string());
             file_data[*cursor_y] = file_data[*cursor_y][..*cursor_x].to_string();
             *cursor_y += 1;
@@ -448,6 +671,11 @@ fn send_command(
     } else if *mode == 'v' {
         if code == KeyCode::Esc {
             *mode = 'n';
+        } else if code == KeyCode::Char(':') || code == KeyCode::Char('!') {
+            // the command line gets the selected lines as '<,'>
+            *last_visual = (*cursor_y.min(visual_y), *cursor_y.max(visual_y));
+            *command_string = if code == KeyCode::Char('!') { "'<,'>!" } else { "'<,'>" }.to_string();
+            *mode = ':';
         } else if code == KeyCode::Char('$') {
             *cursor_x = helper::set_cursor_end(&file_data, *cursor_y);
             *cursor_x = helper::left(*cursor_x);
@@ -469,9 +697,12 @@ fn send_command(
         } else if code == KeyCode::Char('l') {
             *cursor_x = helper::right(&file_data, *cursor_x, *cursor_y);
         } else if code == KeyCode::Char('j') {
+            // a closed fold counts as one line
+            *cursor_y = tree.get_closed_fold(*cursor_y).map_or(*cursor_y, |(_, end)| end);
             *cursor_y = helper::down(&file_data, *cursor_y);
         } else if code == KeyCode::Char('k') {
             *cursor_y = helper::up(*cursor_y);
+            *cursor_y = tree.get_closed_fold(*cursor_y).map_or(*cursor_y, |(start, _)| start);
         } else if code == KeyCode::Char('b') {
             *cursor_x = helper::reset_cursor_end(&file_data, *cursor_x, *cursor_y);
             *cursor_x = helper::get_index_prev_word(&file_data, *cursor_x, *cursor_y);
@@ -482,22 +713,40 @@ fn send_command(
             *cursor_y = 0;
             *prev_keys = "".to_string();
         } else if *prev_keys == "g" && code == KeyCode::Char('c') {
-            let comment_string = match helper::get_comment_string(file_name) {
-                Some(chr) => chr,
-                None => "#",
-            };
-            helper::toggle_comments_in_visual(file_data, comment_string, *cursor_y, *visual_y);
+            match language::get_comment_tokens(language) {
+                (None, None) => *message = format!("No comments in {}", language::get_name(language)),
+                (line_comment, block_comment) => {
+                    helper::toggle_comments(file_data, line_comment, block_comment, (*cursor_y).min(*visual_y), (*cursor_y).max(*visual_y))
+                }
+            }
             *cursor_y = helper::get_cursor_after_visual(*cursor_y, *visual_y);
             *prev_keys = "".to_string();
             *mode = 'n';
-            helper::save_to_file(file_data, file_name, diff_history, *pos);
         } else if *prev_keys == "" && code == KeyCode::Char('g') {
             *prev_keys = "g".to_string();
+        } else if *prev_keys == "i" || *prev_keys == "a" {
+            // selects a text object from the syntax tree, like vaf for the function around the cursor
+            tree.update(language, file_data);
+            let object = match code {
+                KeyCode::Char(object) => tree.get_text_object(file_data, *cursor_x, *cursor_y, *prev_keys == "i", object),
+                _ => Err("".to_string()),
+            };
+            match object {
+                Ok(((begin_x, begin_y), (end_x, end_y))) => {
+                    (*visual_x, *visual_y) = (begin_x, begin_y);
+                    (*cursor_x, *cursor_y) = (end_x, end_y);
+                }
+                Err(err) => *message = err,
+            }
+            *prev_keys = "".to_string();
+        } else if prev_keys.is_empty() && (code == KeyCode::Char('i') || code == KeyCode::Char('a')) {
+            if let KeyCode::Char(c) = code {
+                *prev_keys = c.to_string();
+            }
         } else if code == KeyCode::Char('G') {
             *cursor_y = file_data.len() - 1;
         } else if code == KeyCode::Char('d') && modifiers.contains(KeyModifiers::CONTROL) {
-            let terminal_size = size().unwrap();
-            let term_height = terminal_size.1 as usize;
+            let term_height = *height;
             let mut i = 0;
             while i < term_height {
                 *cursor_y = helper::down(&file_data, *cursor_y);
@@ -507,8 +756,7 @@ fn send_command(
                 i += 2;
             }
         } else if code == KeyCode::Char('u') && modifiers.contains(KeyModifiers::CONTROL) {
-            let terminal_size = size().unwrap();
-            let term_height = terminal_size.1 as usize;
+            let term_height = *height;
             let mut i = 0;
             while i < term_height {
                 *cursor_y = helper::up(*cursor_y);
@@ -519,11 +767,10 @@ fn send_command(
             }
         } else if code == KeyCode::Char('y') {
             *cursor_x = helper::prevent_cursor_end(&file_data, *cursor_x, *cursor_y);
-            helper::copy_in_visual(file_data, *cursor_x, *cursor_y, *visual_x, *visual_y, *mode);
+            error::report(helper::copy_in_visual(file_data, *cursor_x, *cursor_y, *visual_x, *visual_y, *mode, &options.clipboard), message);
             *cursor_y = helper::get_cursor_after_visual(*cursor_y, *visual_y);
             *cursor_x = helper::get_cursor_after_visual(*cursor_x, *visual_x);
             *mode = 'n';
-            helper::save_to_file(file_data, file_name, diff_history, *pos);
         } else if code == KeyCode::Char('c') {
             *cursor_x = helper::prevent_cursor_end(&file_data, *cursor_x, *cursor_y);
             (*cursor_x, *cursor_y) = helper::delete_in_visual(file_data, *cursor_x, *cursor_y, *visual_x, *visual_y, *mode);
@@ -532,51 +779,56 @@ fn send_command(
             *mode = 'i';
         } else if code == KeyCode::Char('d') {
             *cursor_x = helper::prevent_cursor_end(&file_data, *cursor_x, *cursor_y);
-            helper::copy_in_visual(file_data, *cursor_x, *cursor_y, *visual_x, *visual_y, *mode);
+            error::report(helper::copy_in_visual(file_data, *cursor_x, *cursor_y, *visual_x, *visual_y, *mode, &options.clipboard), message);
             (*cursor_x, *cursor_y) = helper::delete_in_visual(file_data, *cursor_x, *cursor_y, *visual_x, *visual_y, *mode);
             *cursor_y = helper::get_cursor_after_visual(*cursor_y, *visual_y);
             *cursor_y = helper::reset_cursor_end_file(file_data.len(), *cursor_y);
             *mode = 'n';
-            helper::save_to_file(file_data, file_name, diff_history, *pos);
         } else if code == KeyCode::Char('x') {
             (*cursor_x, *cursor_y) = helper::delete_in_visual(file_data, *cursor_x, *cursor_y, *visual_x, *visual_y, *mode);
             *cursor_y = helper::get_cursor_after_visual(*cursor_y, *visual_y);
             *cursor_y = helper::reset_cursor_end_file(file_data.len(), *cursor_y);
             *mode = 'n';
-            helper::save_to_file(file_data, file_name, diff_history, *pos);
         }
         helper::log_command(code, modifiers, last_command, *recording);
     } else if *mode == 'V' {
         if code == KeyCode::Esc {
             *mode = 'n';
+        } else if code == KeyCode::Char(':') || code == KeyCode::Char('!') {
+            // the command line gets the selected lines as '<,'>
+            *last_visual = (*cursor_y.min(visual_y), *cursor_y.max(visual_y));
+            *command_string = if code == KeyCode::Char('!') { "'<,'>!" } else { "'<,'>" }.to_string();
+            *mode = ':';
         } else if code == KeyCode::Char('{') {
             *cursor_y = helper::get_prev_empty_line(&file_data, *cursor_y);
         } else if code == KeyCode::Char('}') {
             *cursor_y = helper::get_next_empty_line(&file_data, *cursor_y);
         } else if code == KeyCode::Char('j') {
+            // a closed fold counts as one line
+            *cursor_y = tree.get_closed_fold(*cursor_y).map_or(*cursor_y, |(_, end)| end);
             *cursor_y = helper::down(&file_data, *cursor_y);
         } else if code == KeyCode::Char('k') {
             *cursor_y = helper::up(*cursor_y);
+            *cursor_y = tree.get_closed_fold(*cursor_y).map_or(*cursor_y, |(start, _)| start);
         } else if *prev_keys == "g" && code == KeyCode::Char('g') {
             *cursor_y = 0;
             *prev_keys = "".to_string();
         } else if *prev_keys == "g" && code == KeyCode::Char('c') {
-            let comment_string = match helper::get_comment_string(file_name) {
-                Some(chr) => chr,
-                None => "#",
-            };
-            helper::toggle_comments_in_visual(file_data, comment_string, *cursor_y, *visual_y);
+            match language::get_comment_tokens(language) {
+                (None, None) => *message = format!("No comments in {}", language::get_name(language)),
+                (line_comment, block_comment) => {
+                    helper::toggle_comments(file_data, line_comment, block_comment, (*cursor_y).min(*visual_y), (*cursor_y).max(*visual_y))
+                }
+            }
             *cursor_y = helper::get_cursor_after_visual(*cursor_y, *visual_y);
             *prev_keys = "".to_string();
             *mode = 'n';
-            helper::save_to_file(file_data, file_name, diff_history, *pos);
         } else if *prev_keys == "" && code == KeyCode::Char('g') {
             *prev_keys = "g".to_string();
         } else if code == KeyCode::Char('G') {
             *cursor_y = file_data.len() - 1;
         } else if code == KeyCode::Char('d') && modifiers.contains(KeyModifiers::CONTROL) {
-            let terminal_size = size().unwrap();
-            let term_height = terminal_size.1 as usize;
+            let term_height = *height;
             let mut i = 0;
             while i < term_height {
                 *cursor_y = helper::down(&file_data, *cursor_y);
@@ -586,8 +838,7 @@ fn send_command(
                 i += 2;
             }
         } else if code == KeyCode::Char('u') && modifiers.contains(KeyModifiers::CONTROL) {
-            let terminal_size = size().unwrap();
-            let term_height = terminal_size.1 as usize;
+            let term_height = *height;
             let mut i = 0;
             while i < term_height {
                 *cursor_y = helper::up(*cursor_y);
@@ -597,38 +848,33 @@ fn send_command(
                 i += 2;
             }
         } else if code == KeyCode::Char('>') {
-            helper::increase_indent_visual(file_data, *cursor_y, *visual_y);
-            helper::save_to_file(file_data, file_name, diff_history, *pos);
+            helper::increase_indent_visual(file_data, *cursor_y, *visual_y, indent_width);
             *cursor_y = helper::get_cursor_after_visual(*cursor_y, *visual_y);
             *mode = 'n';
         } else if code == KeyCode::Char('<') {
-            helper::reduce_indent_visual(file_data, *cursor_y, *visual_y);
-            helper::save_to_file(file_data, file_name, diff_history, *pos);
+            helper::reduce_indent_visual(file_data, *cursor_y, *visual_y, indent_width);
             *cursor_y = helper::get_cursor_after_visual(*cursor_y, *visual_y);
             *mode = 'n';
         } else if code == KeyCode::Char('y') {
-            helper::copy_in_visual(file_data, *cursor_x, *cursor_y, *visual_x, *visual_y, *mode);
+            error::report(helper::copy_in_visual(file_data, *cursor_x, *cursor_y, *visual_x, *visual_y, *mode, &options.clipboard), message);
             *cursor_y = helper::get_cursor_after_visual(*cursor_y, *visual_y);
             *mode = 'n';
-            helper::save_to_file(file_data, file_name, diff_history, *pos);
         } else if code == KeyCode::Char('c') {
             helper::delete_in_visual_and_insert(file_data, *cursor_y, *visual_y);
             *cursor_y = helper::get_cursor_after_visual(*cursor_y, *visual_y);
             *cursor_y = helper::reset_cursor_end_file(file_data.len(), *cursor_y);
             *mode = 'i';
         } else if code == KeyCode::Char('d') {
-            helper::copy_in_visual(file_data, *cursor_x, *cursor_y, *visual_x, *visual_y, *mode);
+            error::report(helper::copy_in_visual(file_data, *cursor_x, *cursor_y, *visual_x, *visual_y, *mode, &options.clipboard), message);
             (*cursor_x, *cursor_y) = helper::delete_in_visual(file_data, *cursor_x, *cursor_y, *visual_x, *visual_y, *mode);
             *cursor_y = helper::get_cursor_after_visual(*cursor_y, *visual_y);
             *cursor_y = helper::reset_cursor_end_file(file_data.len(), *cursor_y);
             *mode = 'n';
-            helper::save_to_file(file_data, file_name, diff_history, *pos);
         } else if code == KeyCode::Char('x') {
             (*cursor_x, *cursor_y) = helper::delete_in_visual(file_data, *cursor_x, *cursor_y, *visual_x, *visual_y, *mode);
             *cursor_y = helper::get_cursor_after_visual(*cursor_y, *visual_y);
             *cursor_y = helper::reset_cursor_end_file(file_data.len(), *cursor_y);
             *mode = 'n';
-            helper::save_to_file(file_data, file_name, diff_history, *pos);
         }
         helper::log_command(code, modifiers, last_command, *recording);
     }
@@ -644,122 +890,306 @@ fn main() {
         }
     }));
     let args: Vec<String> = std::env::args().collect();
-    if args.len() < 2 {
-        println!("Please provide a file name");
-        return;
-    }
-    let file_name = &args[1];
-    enable_raw_mode().expect("Failed to enable raw mode");
-    execute!(stdout(), EnterAlternateScreen).expect("Failed to enter alternate screen");
-    let mut file_data = match helper::get_file_data(file_name) {
-        Ok(data) => data,
-        Err(err) => {
-            helper::quit_terminal();
-            println!("Failed to open the file: {}", err);
+    let cli_args = match cli::parse_args(&args[1..]) {
+        Ok(cli::Action::Edit(cli_args)) => cli_args,
+        Ok(cli::Action::Print(text)) => {
+            // ignore a closed pipe, as in editor --help | head
+            let _ = writeln!(stdout(), "{}", text);
             return;
         }
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, cli::USAGE);
+            process::exit(2);
+        }
+    };
+    // stdin is read before the terminal is taken over, keys then come from /dev/tty
+    let stdin_data = if cli_args.files.iter().any(|file| file.name == "-") {
+        match helper::get_stdin_data() {
+            Ok(lines) => lines,
+            Err(err) => {
+                println!("Failed to read stdin: {}", err);
+                return;
+            }
+        }
+    } else {
+        Vec::new()
     };
-    let mut window_line_x = 0;
-    let mut window_line_y = 0;
-    let mut cursor_x = 0;
-    let mut cursor_y = 0;
+    enable_raw_mode().expect("Failed to enable raw mode");
+    execute!(helper::get_terminal_output(), EnterAlternateScreen).expect("Failed to enter alternate screen");
+    let mut buffers: Vec<buffer::Buffer> = Vec::new();
+    let mut message = "".to_string();
+    let mut message_history = Vec::new();
+    let mut options = options::Options::new();
+    let mut keymap = keymap::Keymap::new();
+    let mut config_errors = config::load_config(&mut options, &mut keymap, cli_args.clean);
+    config_errors.extend(language::load_languages(cli_args.clean));
+    if config_errors.len() > 1 {
+        for err in &config_errors {
+            error::add_to_history(err, &mut message_history);
+        }
+        message = format!("{} problems in the config files, see :messages", config_errors.len());
+    } else if let Some(err) = config_errors.first() {
+        message = err.clone();
+    }
+    let height = window::get_layout_size().1;
+    let mut explorer_dir = None;
+    for file in &cli_args.files {
+        if std::path::Path::new(&file.name).is_dir() {
+            explorer_dir = Some(file.name.clone());
+            continue;
+        }
+        let mut buffer = if file.name == "-" {
+            buffer::Buffer::scratch(buffer::STDIN_NAME, stdin_data.clone())
+        } else {
+            match buffer::Buffer::open(&file.name) {
+                Ok(buffer) => {
+                    if buffer.is_new() {
+                        message = format!("\"{}\" [New]", file.name);
+                    }
+                    swap::check_swap_file(&file.name, &buffer.file_data, &mut message);
+                    buffer
+                }
+                Err(err) => {
+                    helper::quit_terminal();
+                    println!("Failed to open the file: {}", err);
+                    return;
+                }
+            }
+        };
+        buffer.readonly |= cli_args.readonly;
+        if let Some(line) = file.line {
+            buffer.cursor_y = helper::reset_cursor_end_file(buffer.file_data.len(), line.max(1) - 1);
+            buffer.cursor_x = (file.col.unwrap_or(1).max(1) - 1).min(buffer.file_data[buffer.cursor_y].len().saturating_sub(1));
+            (buffer.window_line_x, buffer.window_line_y) = helper::center_screen(buffer.cursor_y, height);
+        }
+        buffers.push(buffer);
+    }
+    if let (Some(pattern), Some(buffer)) = (&cli_args.search, buffers.first_mut()) {
+        match helper::find_next_occurrence(&buffer.file_data, 0, 0, pattern) {
+            Some((x, y)) => {
+                (buffer.cursor_x, buffer.cursor_y) = (x, y);
+                (buffer.window_line_x, buffer.window_line_y) = helper::center_screen(y, height);
+            }
+            None => message = format!("Pattern not found: {}", pattern),
+        }
+        buffer.search_string = pattern.clone();
+        buffer.searching = true;
+    }
+    let mut finder = finder::Finder::new();
+    let mut quickfix = quickfix::Quickfix::new();
+    let mut explorer = explorer::Explorer::new(".");
+    // with only directories given the listing takes the whole window, otherwise it opens beside the files
+    let only_dirs = buffers.is_empty() && explorer_dir.is_some();
+    if only_dirs {
+        buffers.push(buffer::Buffer::scratch(explorer::EXPLORER_NAME, Vec::new()));
+    } else if buffers.is_empty() {
+        buffers.push(buffer::Buffer::scratch(buffer::NO_NAME, Vec::new()));
+    }
+    let mut tabs = tab::Tabs::new(window::Layout::new(window::Window::new(&buffers, 0)));
+    if let Some(dir) = explorer_dir {
+        if only_dirs {
+            explorer::open_dir(&dir, &mut explorer, &mut buffers, tabs.current().current());
+        } else {
+            explorer.root = dir;
+            explorer::toggle_window(&mut explorer, &mut buffers, tabs.current());
+        }
+    }
     let mut visual_x = 0;
     let mut visual_y = 0;
     let mut mode = 'n';
     let mut prev_keys = "".to_string();
     let mut last_command: Vec<(KeyCode, KeyModifiers)> = Vec::new();
     let mut recording = true;
-    let mut searching = false;
-    let mut search_string = "".to_string();
     let mut prev_view: Vec<Vec<(char, Color, Color, bool)>> = Vec::new();
     let mut macro_command: Vec<(KeyCode, KeyModifiers)> = Vec::new();
     let mut macro_recording = false;
-    let mut diff_history = diffhist::DiffHistory::new(file_data.clone());
-    let mut pos = (0, 0);
-    if file_data.len() == 0 {
-        file_data.insert(0, "".to_string());
+    let mut command_string = "".to_string();
+    let mut quit = false;
+    for command in &cli_args.commands {
+        if quit {
+            break;
+        }
+        let context = command::CommandContext {
+            buffers: &mut buffers,
+            tabs: &mut tabs,
+            quickfix: &mut quickfix,
+            explorer: &mut explorer,
+            options: &mut options,
+            keymap: &mut keymap,
+            quit: &mut quit,
+        };
+        command::run_command(command, context, &message_history, &mut message);
     }
-    prev_view = helper::render_file_data(
-        prev_view.clone(),
-        file_name,
-        &file_data,
-        window_line_x,
-        window_line_y,
-        cursor_x,
-        cursor_y,
+    error::add_to_history(&message, &mut message_history);
+    tabs.current().update_sizes();
+    tabs.current().update_highlights(&mut buffers);
+    let frame = window::Frame {
         visual_x,
         visual_y,
         mode,
-        search_string.clone(),
-        searching,
         macro_recording,
-        false,
-    );
-    loop {
-        if let Ok(event) = crossterm::event::read() {
-            let mut key_code: Option<KeyCode> = None;
-            let mut key_modifiers: Option<KeyModifiers> = None;
-            let mut resize = false;
-            match event {
-                Event::Key(KeyEvent { code, modifiers, .. }) => {
-                    key_code = Some(code);
-                    key_modifiers = Some(modifiers);
+        prev_keys: "",
+        tabline: tab::get_tabline(&tabs, &buffers, window::get_layout_size().0, &options.get_theme()),
+        overlay: None,
+        command_line: &helper::get_command_line(mode, &command_string, "", &message),
+    };
+    prev_view = window::render_layout(prev_view.clone(), &tabs.layouts[tabs.current_tab], &buffers, &options, &quickfix, frame, false);
+    while !quit {
+        // unsaved changes go to swap files and files are checked for outside changes once the user stops typing,
+        // while big files still loading are redrawn as their lines come in
+        let loading = buffers.iter().any(|buffer| buffer.loading.is_some());
+        let waiting_for_z = mode == 'n' && prev_keys == "z";
+        let delay = if !keymap.pending.is_empty() || waiting_for_z {
+            Duration::from_millis(options.timeoutlen)
+        } else if loading {
+            buffer::LOAD_DELAY
+        } else {
+            swap::SWAP_DELAY
+        };
+        let mut timed_out = false;
+        let event = if crossterm::event::poll(delay).unwrap_or(true) {
+            match crossterm::event::read() {
+                Ok(event) => Some(event),
+                Err(_) => continue,
+            }
+        } else if !keymap.pending.is_empty() || waiting_for_z {
+            // the keys typed so far start a mapping or a z command but no more came
+            timed_out = true;
+            None
+        } else if loading {
+            None
+        } else {
+            swap::write_swap_files(&mut buffers);
+            buffer::check_external_changes(&mut buffers, &mut message);
+            if message.is_empty() {
+                continue;
+            }
+            error::add_to_history(&message, &mut message_history);
+            None
+        };
+        // what the loading threads read so far is taken in on every pass, not only when no key comes
+        let mut load_error = "".to_string();
+        let loaded = loading && buffer::poll_loading(&mut buffers, &mut load_error);
+        if loading && !loaded && event.is_none() && !timed_out {
+            continue;
+        }
+        let full_render = matches!(event, Some(Event::Resize(_, _)));
+        let mut key_code: Option<KeyCode> = None;
+        let mut key_modifiers: Option<KeyModifiers> = None;
+        let mut resize = false;
+        match event {
+            Some(Event::Key(KeyEvent { code, modifiers, .. })) => {
+                key_code = Some(code);
+                key_modifiers = Some(modifiers);
+            },
+            Some(Event::Resize(_, _)) | None => {
+                resize = !timed_out;
+            },
+            _ => break,
+        }
+        if key_code.is_some() && key_code.unwrap() == KeyCode::Char('c') && key_modifiers.unwrap().contains(KeyModifiers::CONTROL) {
+            break;
+        } else {
+            tabs.current().update_sizes();
+            tabs.current().clamp_cursors(&buffers, &options);
+            if !resize {
+                message.clear();
+                buffer::check_external_changes(&mut buffers, &mut message);
+                // typed keys go through the mappings, and mapped keys that may be remapped go through them again
+                let map_mode = keymap::get_map_mode(mode, &prev_keys);
+                let mut keys: VecDeque<(keymap::Key, bool)> = match (key_code, key_modifiers) {
+                    (Some(code), Some(modifiers)) => keymap.feed(map_mode, (code, modifiers)),
+                    _ => keymap.flush(map_mode),
+                }
+                .into();
+                if timed_out && waiting_for_z {
+                    keys.push_back(((KeyCode::Char('z'), KeyModifiers::NONE), false));
+                }
+                let mut mapped = 0;
+                while let Some(((code, modifiers), remap)) = keys.pop_front() {
+                    if remap {
+                        mapped += 1;
+                        if mapped > keymap::MAX_MAP_DEPTH {
+                            keymap.pending.clear();
+                            message = "Recursive mapping".to_string();
+                            break;
+                        }
+                        for key in keymap.feed(keymap::get_map_mode(mode, &prev_keys), (code, modifiers)).into_iter().rev() {
+                            keys.push_front(key);
+                        }
+                        continue;
+                    }
+                    // macros keep the keys after mapping, as they are replayed without the mappings
                     if macro_recording && !(mode == 'n' && code == KeyCode::Char('q')) {
                         macro_command.push((code, modifiers));
                     }
-                },
-                Event::Resize(_, _) => {
-                    resize = true;
-                },
-                _ => break,
-            }
-            if key_code != None && key_code.unwrap() == KeyCode::Char('c') && key_modifiers.unwrap().contains(KeyModifiers::CONTROL) {
-                break;
-            } else {
-                (window_line_x, window_line_y) = helper::calc_window_lines(&file_data, window_line_x, window_line_y, cursor_x, cursor_y);
-                if !resize {
                     send_command(
-                        key_code.unwrap(),
-                        key_modifiers.unwrap(),
-                        &mut file_data,
-                        file_name,
-                        &mut cursor_x,
-                        &mut cursor_y,
+                        code,
+                        modifiers,
+                        &mut buffers,
+                        &mut tabs,
+                        &mut finder,
+                        &mut quickfix,
+                        &mut explorer,
+                        &mut options,
+                        &mut keymap,
                         &mut visual_x,
                         &mut visual_y,
                         &mut mode,
                         &mut prev_keys,
                         &mut last_command,
                         &mut recording,
-                        &mut search_string,
-                        &mut searching,
                         &mut macro_command,
                         &mut macro_recording,
-                        &mut diff_history,
-                        &mut window_line_x,
-                        &mut window_line_y,
-                        &mut pos,
+                        &mut command_string,
+                        &mut quit,
+                        &mut message_history,
+                        &mut message,
                     );
+                    let buffer = tabs.current().current().buffer;
+                    // one undo step per complete command, including a whole insert session
+                    if mode == 'n' && prev_keys.is_empty() {
+                        error::report(buffers[buffer].snapshot(options.autosave), &mut message);
+                    }
+                    if quit {
+                        break;
+                    }
                 }
-                (window_line_x, window_line_y) = helper::calc_window_lines(&file_data, window_line_x, window_line_y, cursor_x, cursor_y);
-                prev_view = helper::render_file_data(
-                    prev_view.clone(),
-                    file_name,
-                    &file_data,
-                    window_line_x,
-                    window_line_y,
-                    cursor_x,
-                    cursor_y,
-                    visual_x,
-                    visual_y,
-                    mode,
-                    search_string.clone(),
-                    searching,
-                    macro_recording,
-                    resize,
-                );
+                error::add_to_history(&message, &mut message_history);
             }
+            if !load_error.is_empty() {
+                message = load_error;
+                error::add_to_history(&message, &mut message_history);
+            }
+            tabs.current().update_sizes();
+            tabs.current().clamp_cursors(&buffers, &options);
+            tabs.current().update_highlights(&mut buffers);
+            let buffer = &buffers[tabs.current().current().buffer];
+            let frame = window::Frame {
+                visual_x,
+                visual_y,
+                mode,
+                macro_recording,
+                prev_keys: &prev_keys,
+                tabline: tab::get_tabline(&tabs, &buffers, window::get_layout_size().0, &options.get_theme()),
+                overlay: if mode == 'f' {
+                    let (width, height) = window::get_layout_size();
+                    finder::get_overlay(&finder, width, height, &options.get_theme())
+                } else {
+                    None
+                },
+                command_line: &helper::get_command_line(mode, &command_string, &buffer.search_string, &message),
+            };
+            prev_view = window::render_layout(prev_view.clone(), &tabs.layouts[tabs.current_tab], &buffers, &options, &quickfix, frame, full_render);
         }
     }
+    swap::delete_own_swap_files(&buffers);
     helper::quit_terminal();
+    if cli_args.stdout || !stdout().is_terminal() {
+        let index = buffer::find_buffer(&buffers, buffer::STDIN_NAME).unwrap_or(tabs.current().current().buffer);
+        if let Err(err) = helper::print_lines(&buffers[index].file_data) {
+            eprintln!("Failed to write to stdout: {}", err);
+            process::exit(1);
+        }
+    }
 }
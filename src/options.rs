@@ -0,0 +1,191 @@
+use crate::keymap;
+use crate::quickfix;
+use crate::statusline;
+use crate::theme;
+
+pub const OPTION_NAMES: &[&str] = &[
+    "autosave",
+    "clipboard",
+    "errorformat",
+    "leader",
+    "makeprg",
+    "number",
+    "scrolloff",
+    "statusline",
+    "tabstop",
+    "theme",
+    "timeoutlen",
+];
+
+// auto uses pbcopy on macOS, wl-copy under Wayland and xclip with xsel otherwise
+pub const CLIPBOARD_PROVIDERS: &[&str] = &["auto", "xclip", "xsel", "wayland", "macos", "tmux"];
+
+// a value from the config file or :set
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Bool(bool),
+    Int(i64),
+    Str(String),
+    List(Vec<String>),
+}
+
+pub struct Options {
+    pub autosave: bool,
+    pub clipboard: String,
+    pub errorformat: Vec<String>,
+    pub leader: String,
+    pub makeprg: String,
+    pub number: bool,
+    pub scrolloff: usize,
+    pub statusline: String,
+    pub tabstop: usize,
+    pub theme: String,
+    // how long to wait for the rest of a mapping, in milliseconds
+    pub timeoutlen: u64,
+}
+
+impl Options {
+    pub fn new() -> Self {
+        Options {
+            autosave: true,
+            clipboard: "auto".to_string(),
+            errorformat: quickfix::DEFAULT_ERROR_FORMATS.iter().map(|format| format.to_string()).collect(),
+            leader: "\\".to_string(),
+            makeprg: quickfix::get_default_makeprg(),
+            number: true,
+            scrolloff: 0,
+            statusline: statusline::DEFAULT_STATUSLINE.to_string(),
+            tabstop: 4,
+            theme: "dark".to_string(),
+            timeoutlen: 1000,
+        }
+    }
+
+    pub fn get_theme(&self) -> theme::Theme {
+        theme::get_theme(&self.theme).unwrap_or_else(|| theme::get_theme("dark").unwrap())
+    }
+
+    // checks the value before using it, so a bad config line leaves the default in place
+    pub fn set(&mut self, name: &str, value: Value) -> Result<(), String> {
+        let name = get_full_name(name);
+        match (name, value) {
+            ("autosave", Value::Bool(value)) => self.autosave = value,
+            ("number", Value::Bool(value)) => self.number = value,
+            ("tabstop", Value::Int(value)) if (1..=16).contains(&value) => self.tabstop = value as usize,
+            ("tabstop", Value::Int(value)) => return Err(format!("tabstop must be from 1 to 16, not {}", value)),
+            ("scrolloff", Value::Int(value)) if (0..=999).contains(&value) => self.scrolloff = value as usize,
+            ("scrolloff", Value::Int(value)) => return Err(format!("scrolloff must be from 0 to 999, not {}", value)),
+            ("theme", Value::Str(value)) if theme::get_theme(&value).is_some() => self.theme = value,
+            ("theme", Value::Str(value)) => {
+                return Err(format!("Unknown theme {:?}, expected one of {}", value, theme::THEME_NAMES.join(", ")));
+            }
+            ("clipboard", Value::Str(value)) if CLIPBOARD_PROVIDERS.contains(&value.as_str()) => self.clipboard = value,
+            ("clipboard", Value::Str(value)) => {
+                return Err(format!("Unknown clipboard {:?}, expected one of {}", value, CLIPBOARD_PROVIDERS.join(", ")));
+            }
+            ("timeoutlen", Value::Int(value)) if (0..=10000).contains(&value) => self.timeoutlen = value as u64,
+            ("timeoutlen", Value::Int(value)) => return Err(format!("timeoutlen must be from 0 to 10000, not {}", value)),
+            // mappings read <leader> when they are defined, so set it before them
+            ("leader", Value::Str(value)) => self.leader = keymap::format_keys(&keymap::parse_keys(&value, "")?),
+            ("statusline", Value::Str(value)) => self.statusline = value,
+            ("makeprg", Value::Str(value)) => self.makeprg = value,
+            ("errorformat", Value::List(formats)) => self.errorformat = formats,
+            ("errorformat", Value::Str(value)) => self.errorformat = value.split(',').map(|format| format.to_string()).collect(),
+            (name, _) if OPTION_NAMES.contains(&name) => {
+                return Err(format!("{} expects {}", name, get_type_name(name)));
+            }
+            (name, _) => return Err(get_unknown_message(name)),
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<String> {
+        Some(match get_full_name(name) {
+            "autosave" => self.autosave.to_string(),
+            "clipboard" => self.clipboard.clone(),
+            "errorformat" => self.errorformat.join(","),
+            "leader" => self.leader.clone(),
+            "makeprg" => self.makeprg.clone(),
+            "number" => self.number.to_string(),
+            "scrolloff" => self.scrolloff.to_string(),
+            "statusline" => self.statusline.clone(),
+            "tabstop" => self.tabstop.to_string(),
+            "theme" => self.theme.clone(),
+            "timeoutlen" => self.timeoutlen.to_string(),
+            _ => return None,
+        })
+    }
+}
+
+// the short names vim users type
+fn get_full_name(name: &str) -> &str {
+    match name {
+        "ts" => "tabstop",
+        "so" => "scrolloff",
+        "nu" => "number",
+        "efm" => "errorformat",
+        "mp" => "makeprg",
+        "stl" => "statusline",
+        "tm" => "timeoutlen",
+        _ => name,
+    }
+}
+
+fn get_type_name(name: &str) -> &'static str {
+    match get_full_name(name) {
+        "autosave" | "number" => "true or false",
+        "tabstop" | "scrolloff" | "timeoutlen" => "a number",
+        "errorformat" => "a list of strings",
+        _ => "a string",
+    }
+}
+
+pub fn get_unknown_message(name: &str) -> String {
+    match OPTION_NAMES.iter().min_by_key(|option| get_edit_distance(name, option)) {
+        Some(option) if get_edit_distance(name, option) <= 2 => format!("Unknown option {}, did you mean {}?", name, option),
+        _ => format!("Unknown option {}", name),
+    }
+}
+
+pub fn get_edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let cost = if a_char == *b_char { 0 } else { 1 };
+            current.push((prev[j] + cost).min(prev[j + 1] + 1).min(current[j] + 1));
+        }
+        prev = current;
+    }
+    prev[b.len()]
+}
+
+// :set name, :set noname, :set name=value; the value is read as the type the option expects
+pub fn parse_set_arg(arg: &str) -> Result<(String, Value), String> {
+    let (name, value) = match arg.split_once('=') {
+        Some((name, value)) => (name.trim(), Some(value.trim())),
+        None => (arg.trim(), None),
+    };
+    let is_bool = |name: &str| get_type_name(name) == "true or false" && OPTION_NAMES.contains(&get_full_name(name));
+    match value {
+        None if is_bool(name) => Ok((name.to_string(), Value::Bool(true))),
+        None => match name.strip_prefix("no") {
+            Some(name) if is_bool(name) => Ok((name.to_string(), Value::Bool(false))),
+            _ if OPTION_NAMES.contains(&get_full_name(name)) => Err(format!("{} expects {}", name, get_type_name(name))),
+            _ => Err(get_unknown_message(name)),
+        },
+        Some(value) => match get_type_name(name) {
+            "true or false" => match value {
+                "true" | "on" | "yes" => Ok((name.to_string(), Value::Bool(true))),
+                "false" | "off" | "no" => Ok((name.to_string(), Value::Bool(false))),
+                _ => Err(format!("{} expects true or false, not {}", name, value)),
+            },
+            "a number" => match value.parse() {
+                Ok(number) => Ok((name.to_string(), Value::Int(number))),
+                Err(_) => Err(format!("{} expects a number, not {}", name, value)),
+            },
+            _ => Ok((name.to_string(), Value::Str(value.to_string()))),
+        },
+    }
+}
@@ -0,0 +1,388 @@
+use std::path::Path;
+use std::process::{Command, Stdio};
+use crate::buffer;
+use crate::command;
+use crate::finder;
+use crate::helper;
+use crate::text::TextBuffer;
+use crate::window;
+
+pub const QUICKFIX_NAME: &str = "[Quickfix List]";
+
+// %f file, %l line, %c column, %t error type, %m message, %* anything; a space matches any run of whitespace
+pub const DEFAULT_ERROR_FORMATS: &[&str] = &[
+    "--> %f:%l:%c",
+    "%f(%l,%c): %t %m",
+    "%f:%l:%c - %t %m",
+    "%f:%l:%c: %t: %m",
+    "%f:%l: %t: %m",
+    "%f:%l:%c: %m",
+    "%l:%c %t %m",
+    "%t[%*]: %m",
+    "%t: %m",
+    "%f",
+];
+
+pub struct QuickfixEntry {
+    pub file_name: String,
+    pub line: usize,
+    pub col: usize,
+    pub kind: String,
+    pub text: String,
+}
+
+pub struct Quickfix {
+    pub entries: Vec<QuickfixEntry>,
+    pub current: usize,
+}
+
+impl Quickfix {
+    pub fn new() -> Self {
+        Quickfix {
+            entries: Vec::new(),
+            current: 0,
+        }
+    }
+
+    pub fn get_lines(&self) -> Vec<String> {
+        self.entries
+            .iter()
+            .map(|entry| {
+                let location = format!("{}:{}:{}", entry.file_name, entry.line + 1, entry.col + 1);
+                if entry.kind.is_empty() {
+                    format!("{}: {}", location, entry.text.trim())
+                } else {
+                    format!("{}: {}: {}", location, entry.kind, entry.text.trim())
+                }
+            })
+            .collect()
+    }
+
+    // gutter marks for the errors and warnings in file_name
+    pub fn get_signs(&self, file_name: &str) -> Vec<(usize, char)> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.file_name == file_name || Path::new(&entry.file_name) == Path::new(file_name))
+            .filter_map(|entry| match entry.kind.chars().next() {
+                Some('e') | Some('E') => Some((entry.line, 'E')),
+                Some('w') | Some('W') => Some((entry.line, 'W')),
+                _ => None,
+            })
+            .collect()
+    }
+}
+
+pub fn get_default_makeprg() -> String {
+    if Path::new("Cargo.toml").is_file() {
+        "cargo build".to_string()
+    } else if Path::new("tsconfig.json").is_file() {
+        "npx tsc --pretty false".to_string()
+    } else if Path::new("package.json").is_file() {
+        "npx eslint .".to_string()
+    } else {
+        "make".to_string()
+    }
+}
+
+#[derive(Default)]
+struct ErrorMatch {
+    file_name: Option<String>,
+    line: Option<usize>,
+    col: Option<usize>,
+    kind: Option<String>,
+    message: Option<String>,
+}
+
+fn match_tokens(format: &[char], line: &[char], captures: &mut ErrorMatch) -> bool {
+    if format.is_empty() {
+        return line.is_empty();
+    }
+    if format[0] == ' ' {
+        let spaces = line.iter().take_while(|c| c.is_whitespace()).count();
+        return spaces > 0 && match_tokens(&format[1..], &line[spaces..], captures);
+    }
+    if format[0] != '%' || format.len() < 2 {
+        return !line.is_empty() && line[0] == format[0] && match_tokens(&format[1..], &line[1..], captures);
+    }
+    let rest = &format[2..];
+    match format[1] {
+        'l' | 'c' => {
+            let digits = line.iter().take_while(|c| c.is_ascii_digit()).count();
+            if digits == 0 {
+                return false;
+            }
+            let number: usize = line[..digits].iter().collect::<String>().parse().unwrap_or(1);
+            if format[1] == 'l' {
+                captures.line = Some(number);
+            } else {
+                captures.col = Some(number);
+            }
+            match_tokens(rest, &line[digits..], captures)
+        }
+        't' => {
+            let letters = line.iter().take_while(|c| c.is_alphabetic()).count();
+            for end in (1..=letters).rev() {
+                if match_tokens(rest, &line[end..], captures) {
+                    captures.kind = Some(line[..end].iter().collect());
+                    return true;
+                }
+            }
+            false
+        }
+        'f' | 'm' | '*' => {
+            // shortest match that lets the rest of the format succeed
+            for end in 1..=line.len() {
+                if match_tokens(rest, &line[end..], captures) {
+                    let text: String = line[..end].iter().collect();
+                    if format[1] == 'f' {
+                        captures.file_name = Some(text);
+                    } else if format[1] == 'm' {
+                        captures.message = Some(text);
+                    }
+                    return true;
+                }
+            }
+            false
+        }
+        '%' => !line.is_empty() && line[0] == '%' && match_tokens(rest, &line[1..], captures),
+        _ => false,
+    }
+}
+
+fn match_error_format(format: &str, line: &str) -> Option<ErrorMatch> {
+    let format: Vec<char> = format.chars().collect();
+    let line: Vec<char> = line.trim().chars().collect();
+    let mut captures = ErrorMatch::default();
+    if match_tokens(&format, &line, &mut captures) {
+        Some(captures)
+    } else {
+        None
+    }
+}
+
+// a line with only a message (rustc) or only a file (eslint) is remembered for the lines after it
+pub fn parse_errors(output: &str, error_formats: &[String]) -> Vec<QuickfixEntry> {
+    let mut entries = Vec::new();
+    let mut pending_message: Option<(String, String)> = None;
+    let mut pending_file: Option<String> = None;
+    for line in output.lines() {
+        for format in error_formats {
+            let captures = match match_error_format(format, line) {
+                Some(captures) => captures,
+                None => continue,
+            };
+            if let Some(file_name) = &captures.file_name {
+                if !Path::new(file_name).is_file() {
+                    continue;
+                }
+            }
+            let kind = captures.kind.clone().unwrap_or_default();
+            match (captures.file_name, captures.line, captures.message) {
+                (Some(file_name), Some(line), message) => {
+                    let (kind, text) = match (message, pending_message.take()) {
+                        (Some(message), _) => (kind, message),
+                        (None, Some(pending)) => pending,
+                        (None, None) => (kind, "".to_string()),
+                    };
+                    entries.push(QuickfixEntry {
+                        file_name,
+                        line: line.saturating_sub(1),
+                        col: captures.col.unwrap_or(1).saturating_sub(1),
+                        kind,
+                        text,
+                    });
+                }
+                (None, Some(line), Some(message)) => {
+                    if let Some(file_name) = &pending_file {
+                        entries.push(QuickfixEntry {
+                            file_name: file_name.clone(),
+                            line: line.saturating_sub(1),
+                            col: captures.col.unwrap_or(1).saturating_sub(1),
+                            kind,
+                            text: message,
+                        });
+                    }
+                }
+                (Some(file_name), None, None) => pending_file = Some(file_name),
+                (None, None, Some(message)) => pending_message = Some((kind, message)),
+                _ => (),
+            }
+            break;
+        }
+    }
+    entries
+}
+
+// runs makeprg through the shell and returns its combined output
+pub fn run_make(makeprg: &str, args: &str) -> Result<String, String> {
+    let command = format!("{} {} 2>&1", makeprg, args);
+    match Command::new("sh").arg("-c").arg(&command).stdin(Stdio::null()).output() {
+        Ok(output) => Ok(String::from_utf8_lossy(&output.stdout).to_string()),
+        Err(err) => Err(format!("Failed to run {}: {}", makeprg, err)),
+    }
+}
+
+// plain substring search over the files the finder would list
+pub fn grep_project(root: &str, pattern: &str) -> Vec<QuickfixEntry> {
+    let mut entries = Vec::new();
+    for file_name in finder::get_project_files(root) {
+        let file_data = match helper::get_file_data(&file_name) {
+            Ok(file_data) => file_data,
+            Err(_) => continue,
+        };
+        for (line, text) in file_data.iter().enumerate() {
+            if let Some(col) = text.find(pattern) {
+                entries.push(QuickfixEntry {
+                    file_name: file_name.clone(),
+                    line,
+                    col,
+                    kind: "".to_string(),
+                    text: text.to_string(),
+                });
+            }
+        }
+    }
+    entries
+}
+
+// `:vimgrep /pattern/` and `:grep pattern` both take the pattern, optionally delimited
+pub fn parse_pattern(arg: &str) -> &str {
+    let arg = arg.trim();
+    if arg.len() >= 2 && arg.starts_with('/') {
+        if let Some(end) = arg[1..].find('/') {
+            return &arg[1..end + 1];
+        }
+    }
+    if arg.len() >= 2 && arg.starts_with('"') && arg.ends_with('"') {
+        return &arg[1..arg.len() - 1];
+    }
+    arg
+}
+
+pub fn set_entries(quickfix: &mut Quickfix, entries: Vec<QuickfixEntry>, buffers: &mut [buffer::Buffer]) {
+    quickfix.entries = entries;
+    quickfix.current = 0;
+    if let Some(index) = buffer::find_buffer(buffers, QUICKFIX_NAME) {
+        buffers[index].set_data(quickfix.get_lines());
+    }
+}
+
+pub fn open_window(quickfix: &Quickfix, buffers: &mut Vec<buffer::Buffer>, layout: &mut window::Layout) {
+    let index = match buffer::find_buffer(buffers, QUICKFIX_NAME) {
+        Some(index) => index,
+        None => {
+            buffers.push(buffer::Buffer::scratch(QUICKFIX_NAME, quickfix.get_lines()));
+            buffers.len() - 1
+        }
+    };
+    if let Some(window) = layout.find_window(index) {
+        layout.current_window = window;
+    } else {
+        layout.split_bottom(window::Window::new(buffers, index), 10);
+    }
+}
+
+pub fn close_window(buffers: &[buffer::Buffer], layout: &mut window::Layout) {
+    if let Some(index) = buffer::find_buffer(buffers, QUICKFIX_NAME) {
+        if let Some(window) = layout.find_window(index) {
+            layout.close_window(window);
+        }
+    }
+}
+
+// opens the entry in the last used window that isn't the quickfix window
+pub fn jump_to_entry(
+    quickfix: &mut Quickfix,
+    entry: usize,
+    buffers: &mut Vec<buffer::Buffer>,
+    layout: &mut window::Layout,
+    message: &mut String,
+    ) {
+    if entry >= quickfix.entries.len() {
+        *message = "No Errors".to_string();
+        return;
+    }
+    quickfix.current = entry;
+    let quickfix_buffer = buffer::find_buffer(buffers, QUICKFIX_NAME);
+    if Some(layout.current().buffer) == quickfix_buffer {
+        layout.current().cursor_y = entry;
+        match layout.windows.iter().position(|window| Some(window.buffer) != quickfix_buffer) {
+            Some(window) => layout.current_window = window,
+            None => layout.split(false),
+        }
+    }
+    let QuickfixEntry { file_name, line, col, text, .. } = &quickfix.entries[entry];
+    command::edit_file(file_name, buffers, layout.current(), message);
+    let window = layout.current();
+    if buffers[window.buffer].file_name == *file_name {
+        window.cursor_y = helper::reset_cursor_end_file(buffers[window.buffer].file_data.len(), *line);
+        window.cursor_x = *col;
+        *message = format!("({} of {}): {}", entry + 1, quickfix.entries.len(), text.trim());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn formats() -> Vec<String> {
+        DEFAULT_ERROR_FORMATS.iter().map(|format| format.to_string()).collect()
+    }
+
+    fn summary(entries: &[QuickfixEntry]) -> Vec<(String, usize, usize, String, String)> {
+        entries
+            .iter()
+            .map(|entry| (entry.file_name.clone(), entry.line, entry.col, entry.kind.clone(), entry.text.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn matches_tokens() {
+        let captures = match_error_format("%f:%l:%c: %m", "src/main.rs:10:5: something went wrong").unwrap();
+        assert_eq!(captures.file_name.as_deref(), Some("src/main.rs"));
+        assert_eq!((captures.line, captures.col), (Some(10), Some(5)));
+        assert_eq!(captures.message.as_deref(), Some("something went wrong"));
+        let captures = match_error_format("%l:%c %t %m", "  3:5    warning  Unused x  no-unused").unwrap();
+        assert_eq!(captures.kind.as_deref(), Some("warning"));
+        assert_eq!(captures.message.as_deref(), Some("Unused x  no-unused"));
+        assert!(match_error_format("%f:%l:%c: %m", "src/main.rs:x:5: text").is_none());
+        assert!(match_error_format("%t[%*]: %m", "error[E0425]: cannot find").is_some());
+        assert!(match_error_format("100%% %m", "100% done").is_some());
+        assert!(match_error_format("a b", "ab").is_none());
+    }
+
+    #[test]
+    fn parses_rustc_errors() {
+        let output = "error[E0425]: cannot find value `x` in this scope\n  --> src/main.rs:3:5\n   |\nwarning: unused variable: `y`\n --> src/text.rs:10:9\n";
+        assert_eq!(summary(&parse_errors(output, &formats())), vec![
+            ("src/main.rs".to_string(), 2, 4, "error".to_string(), "cannot find value `x` in this scope".to_string()),
+            ("src/text.rs".to_string(), 9, 8, "warning".to_string(), "unused variable: `y`".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn parses_tsc_and_eslint_errors() {
+        let output = "src/main.rs(3,5): error TS1005: ';' expected.\nsrc/text.rs\n  7:1  warning  Missing semicolon  semi\n";
+        assert_eq!(summary(&parse_errors(output, &formats())), vec![
+            ("src/main.rs".to_string(), 2, 4, "error".to_string(), "TS1005: ';' expected.".to_string()),
+            ("src/text.rs".to_string(), 6, 0, "warning".to_string(), "Missing semicolon  semi".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn skips_files_that_do_not_exist() {
+        let output = "no/such/file.rs:1:1: error: missing\nsrc/main.rs:2:1: error: here\n";
+        let entries = parse_errors(output, &formats());
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].file_name, "src/main.rs");
+        assert_eq!(entries[0].text, "here");
+    }
+
+    #[test]
+    fn parses_patterns() {
+        assert_eq!(parse_pattern(" /fn main/ "), "fn main");
+        assert_eq!(parse_pattern("\"a b\""), "a b");
+        assert_eq!(parse_pattern("plain"), "plain");
+        assert_eq!(parse_pattern("/"), "/");
+    }
+}
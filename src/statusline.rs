@@ -0,0 +1,95 @@
+use std::path::Path;
+use crate::buffer;
+use crate::text::TextBuffer;
+use crate::window;
+
+// %M mode, %f file, %m modified, %r read only or still loading, %l line, %c column, %L line count, %P percentage, %y file type,
+// %e line endings, %k pending keys, %q macro recording, %= right align the rest, %% a literal %
+pub const DEFAULT_STATUSLINE: &str = " %M  %f%m%r%=%k  %q  %y  %e  %l:%c  %P ";
+
+pub fn get_mode_name(mode: char) -> &'static str {
+    match mode {
+        'n' => "NORMAL",
+        'i' => "INSERT",
+        'v' => "VISUAL",
+        'V' => "V-LINE",
+        ':' => "COMMAND",
+        '/' => "SEARCH",
+        'f' => "FINDER",
+        _ => "",
+    }
+}
+
+// the detected language, or the extension for files of no known language
+pub fn get_file_type(buffer: &buffer::Buffer) -> &str {
+    match buffer.language {
+        Some(language) => &language.name,
+        None => Path::new(&buffer.file_name).extension().and_then(|extension| extension.to_str()).unwrap_or(""),
+    }
+}
+
+fn get_percentage(window: &window::Window, line_count: usize) -> String {
+    if line_count <= 1 {
+        "All".to_string()
+    } else {
+        format!("{}%", window.cursor_y * 100 / (line_count - 1))
+    }
+}
+
+// mode, pending keys and recording are only shown for the current window, which passes its mode
+pub fn format_status(
+    format: &str,
+    width: usize,
+    window: &window::Window,
+    buffer: &buffer::Buffer,
+    mode: Option<char>,
+    prev_keys: &str,
+    macro_recording: bool,
+    ) -> String {
+    let mut left = String::new();
+    let mut right = String::new();
+    let mut aligned = false;
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        let text = if c != '%' {
+            c.to_string()
+        } else {
+            match chars.next() {
+                Some('M') => mode.map(get_mode_name).unwrap_or("").to_string(),
+                Some('f') => buffer.file_name.clone(),
+                Some('m') => if buffer.modified { " [+]".to_string() } else { "".to_string() },
+                Some('r') => if buffer.loading.is_some() {
+                    " [loading]".to_string()
+                } else if buffer.readonly {
+                    " [RO]".to_string()
+                } else {
+                    "".to_string()
+                },
+                Some('l') => (window.cursor_y + 1).to_string(),
+                Some('c') => (window.cursor_x + 1).to_string(),
+                Some('L') => buffer.file_data.len().to_string(),
+                Some('P') => get_percentage(window, buffer.file_data.len()),
+                Some('y') => get_file_type(buffer).to_string(),
+                Some('e') => if buffer.line_ending == "\r\n" { "dos".to_string() } else { "unix".to_string() },
+                Some('k') if mode.is_some() => prev_keys.to_string(),
+                Some('q') if mode.is_some() && macro_recording => "recording".to_string(),
+                Some('=') => {
+                    aligned = true;
+                    continue;
+                }
+                Some('%') => "%".to_string(),
+                _ => "".to_string(),
+            }
+        };
+        if aligned {
+            right.push_str(&text);
+        } else {
+            left.push_str(&text);
+        }
+    }
+    let used = left.chars().count() + right.chars().count();
+    if used < width {
+        left.push_str(&" ".repeat(width - used));
+    }
+    left + &right
+}
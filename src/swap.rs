@@ -0,0 +1,129 @@
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::process::{self, Command, Stdio};
+use std::thread;
+use std::time::Duration;
+use crate::buffer;
+use crate::diffhist;
+use crate::text::{LineRope, TextBuffer};
+
+pub const SWAP_DELAY: Duration = Duration::from_secs(4);
+pub const SWAP_DIFF_NAME: &str = "[Swap Diff]";
+
+fn get_swap_dir() -> PathBuf {
+    match env::var("XDG_STATE_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir).join("editor/swap"),
+        _ => match env::var("HOME") {
+            Ok(home) => PathBuf::from(home).join(".local/state/editor/swap"),
+            Err(_) => env::temp_dir().join("editor-swap"),
+        },
+    }
+}
+
+// one swap file per absolute path, with the slashes escaped like vim does
+pub fn get_swap_path(file_name: &str) -> PathBuf {
+    let path = fs::canonicalize(file_name).unwrap_or_else(|_| PathBuf::from(file_name));
+    let name = path.to_string_lossy().replace('/', "%");
+    get_swap_dir().join(format!("{}.swp", name))
+}
+
+pub fn hash_data(file_data: &LineRope) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    file_data.len().hash(&mut hasher);
+    for line in file_data.iter() {
+        line.hash(&mut hasher);
+    }
+    hasher.finish()
+}
+
+// the first line is the pid of the editor that wrote it, the rest is the buffer
+pub fn write_swap_files(buffers: &mut [buffer::Buffer]) {
+    for buffer in buffers.iter_mut() {
+        if buffer.scratch {
+            continue;
+        }
+        let hash = hash_data(&buffer.file_data);
+        if hash == buffer.swap_hash {
+            continue;
+        }
+        buffer.swap_hash = hash;
+        let swap_path = get_swap_path(&buffer.file_name);
+        let contents = format!("{}\n{}", process::id(), buffer.file_data.iter().map(|line| line.as_str()).collect::<Vec<&str>>().join("\n"));
+        thread::spawn(move || {
+            if let Some(dir) = swap_path.parent() {
+                let _ = fs::create_dir_all(dir);
+            }
+            let _ = fs::write(swap_path, contents);
+        });
+    }
+}
+
+pub fn delete_swap_file(file_name: &str) {
+    let _ = fs::remove_file(get_swap_path(file_name));
+}
+
+// on a clean exit, leaving alone the swap files another editor is writing
+pub fn delete_own_swap_files(buffers: &[buffer::Buffer]) {
+    for buffer in buffers.iter().filter(|buffer| !buffer.scratch) {
+        if read_swap_file(&buffer.file_name).is_some_and(|(pid, _)| pid == process::id()) {
+            delete_swap_file(&buffer.file_name);
+        }
+    }
+}
+
+pub fn read_swap_file(file_name: &str) -> Option<(u32, Vec<String>)> {
+    let contents = fs::read_to_string(get_swap_path(file_name)).ok()?;
+    let (pid, data) = contents.split_once('\n').unwrap_or((&contents, ""));
+    Some((pid.parse().ok()?, data.split('\n').map(|line| line.to_string()).collect()))
+}
+
+fn is_running(pid: u32) -> bool {
+    if Path::new("/proc").is_dir() {
+        return Path::new(&format!("/proc/{}", pid)).exists();
+    }
+    Command::new("kill")
+        .args(["-0", &pid.to_string()])
+        .stderr(Stdio::null())
+        .status()
+        .map(|status| status.success())
+        .unwrap_or(false)
+}
+
+// a swap file left behind by an editor that is no longer running, if it differs from disk
+pub fn find_stale_swap(file_name: &str, file_data: &LineRope) -> Option<Vec<String>> {
+    let (pid, swap_data) = read_swap_file(file_name)?;
+    if pid == process::id() || is_running(pid) {
+        return None;
+    }
+    if *file_data == swap_data {
+        delete_swap_file(file_name);
+        return None;
+    }
+    Some(swap_data)
+}
+
+pub fn check_swap_file(file_name: &str, file_data: &LineRope, message: &mut String) {
+    if let Some((pid, _)) = read_swap_file(file_name) {
+        if pid != process::id() && is_running(pid) {
+            *message = format!("{} is being edited by process {}", file_name, pid);
+            return;
+        }
+    }
+    if find_stale_swap(file_name, file_data).is_some() {
+        *message = format!(
+            "Found swap file for {}: :recover to restore it, :diffswap to compare, :delswap to delete",
+            file_name,
+        );
+    }
+}
+
+// lines of the file on disk marked '-' and the swap file marked '+'
+pub fn get_swap_diff(file_name: &str, file_data: &LineRope) -> Option<Vec<String>> {
+    let (_, swap_data) = read_swap_file(file_name)?;
+    let mut lines = vec![format!("--- {}", file_name), format!("+++ {}", get_swap_path(file_name).display())];
+    lines.extend(diffhist::diff_lines(&file_data.to_vec(), &swap_data));
+    Some(lines)
+}
@@ -0,0 +1,303 @@
+use crossterm::style::Color;
+use std::cmp::Reverse;
+use std::ptr;
+use crate::language::{self, Language};
+use crate::text::{LineRope, TextBuffer};
+use crate::theme::Theme;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Kind {
+    Text,
+    Comment,
+    String,
+    Number,
+    Keyword,
+    Type,
+    Constant,
+    Bracket,
+    Brace,
+    Paren,
+}
+
+// what a line starts inside of, carried over from the end of the line before
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Code,
+    // how many block comments deep, more than one only where they nest
+    Comment(usize),
+    // the index of the string rule in the grammar
+    String(usize),
+}
+
+struct StringRule<'a> {
+    open: &'a str,
+    close: &'a str,
+    escapes: bool,
+    multiline: bool,
+}
+
+// the language's tokens gathered up for the tokenizer
+struct Grammar<'a> {
+    line_comment: Option<&'a str>,
+    block_comment: Option<(&'a str, &'a str)>,
+    nested_comments: bool,
+    strings: Vec<StringRule<'a>>,
+    char_literals: bool,
+    keywords: &'a [String],
+    types: &'a [String],
+    constants: &'a [String],
+}
+
+impl<'a> Grammar<'a> {
+    fn new(language: Option<&'a Language>) -> Self {
+        let (line_comment, block_comment) = language::get_comment_tokens(language);
+        let mut strings = Vec::new();
+        match language {
+            Some(language) => {
+                for (rules, escapes, multiline) in [
+                    (&language.strings, true, false),
+                    (&language.multiline_strings, true, true),
+                    (&language.raw_strings, false, true),
+                ] {
+                    strings.extend(rules.iter().map(|(open, close)| StringRule { open, close, escapes, multiline }));
+                }
+            }
+            None => strings.extend(
+                language::DEFAULT_STRINGS
+                    .iter()
+                    .map(|quote| StringRule { open: quote, close: quote, escapes: true, multiline: false }),
+            ),
+        }
+        // the longest opener wins, so """ is not an empty string followed by a quote
+        strings.sort_by_key(|rule| Reverse(rule.open.len()));
+        Grammar {
+            line_comment,
+            block_comment,
+            nested_comments: language.is_some_and(|language| language.nested_comments),
+            strings,
+            char_literals: language.is_some_and(|language| language.char_literals),
+            keywords: language.map_or(&[], |language| &language.keywords[..]),
+            types: language.map_or(&[], |language| &language.types[..]),
+            constants: language.map_or(&[], |language| &language.constants[..]),
+        }
+    }
+}
+
+fn is_word(chr: char) -> bool {
+    chr.is_alphanumeric() || chr == '_'
+}
+
+fn starts_with_at(chars: &[char], index: usize, token: &str) -> bool {
+    token.chars().enumerate().all(|(offset, chr)| chars.get(index + offset) == Some(&chr))
+}
+
+// like starts_with_at, but a token that starts with a letter, like REM or r", does not start inside a word
+// and one that is a whole word does not match the start of a longer one
+fn starts_token_at(chars: &[char], index: usize, token: &str) -> bool {
+    if !starts_with_at(chars, index, token) {
+        return false;
+    }
+    let len = token.chars().count();
+    let inside_word = token.starts_with(is_word) && index > 0 && is_word(chars[index - 1]);
+    let longer_word = token.chars().all(is_word) && chars.get(index + len).is_some_and(|chr| is_word(*chr));
+    !inside_word && !longer_word
+}
+
+fn fill(kinds: &mut [Kind], start: usize, len: usize, kind: Kind) {
+    let end = (start + len).min(kinds.len());
+    kinds[start..end].fill(kind);
+}
+
+// 'a' or '\n', None for a ' that starts something else like a lifetime
+fn get_char_literal_len(chars: &[char], index: usize) -> Option<usize> {
+    match chars.get(index + 1)? {
+        '\\' => chars[index + 2..].iter().take(10).position(|chr| *chr == '\'').map(|offset| offset + 3),
+        '\'' => None,
+        _ if chars.get(index + 2) == Some(&'\'') => Some(3),
+        _ => None,
+    }
+}
+
+// the kind of every character on the line and what the next line starts in
+fn highlight_line(grammar: &Grammar, line: &str, mut state: State) -> (Vec<Kind>, State) {
+    let chars: Vec<char> = line.chars().collect();
+    let mut kinds = vec![Kind::Text; chars.len()];
+    let mut index = 0;
+    while index < chars.len() {
+        match state {
+            State::Comment(depth) => {
+                let (open, close) = grammar.block_comment.unwrap_or(("", ""));
+                let len = if starts_with_at(&chars, index, close) {
+                    state = if depth > 1 { State::Comment(depth - 1) } else { State::Code };
+                    close.chars().count()
+                } else if grammar.nested_comments && starts_with_at(&chars, index, open) {
+                    state = State::Comment(depth + 1);
+                    open.chars().count()
+                } else {
+                    1
+                };
+                fill(&mut kinds, index, len, Kind::Comment);
+                index += len;
+            }
+            State::String(rule) => {
+                let rule = &grammar.strings[rule];
+                let len = if rule.escapes && chars[index] == '\\' {
+                    2
+                } else if starts_with_at(&chars, index, rule.close) {
+                    state = State::Code;
+                    rule.close.chars().count()
+                } else {
+                    1
+                };
+                fill(&mut kinds, index, len, Kind::String);
+                index += len;
+            }
+            State::Code => index = highlight_code(grammar, &chars, index, &mut kinds, &mut state),
+        }
+    }
+    // a quote that only lasts a line ends with it even when it is not closed
+    if let State::String(rule) = state {
+        if !grammar.strings[rule].multiline {
+            state = State::Code;
+        }
+    }
+    (kinds, state)
+}
+
+// the token at the index outside of any comment or string, returns where the next one starts
+fn highlight_code(grammar: &Grammar, chars: &[char], index: usize, kinds: &mut [Kind], state: &mut State) -> usize {
+    // the block opener goes first as it can start with the line comment, like --[[ in lua
+    if let Some((open, _)) = grammar.block_comment.filter(|(open, _)| starts_token_at(chars, index, open)) {
+        *state = State::Comment(1);
+        let len = open.chars().count();
+        fill(kinds, index, len, Kind::Comment);
+        return index + len;
+    }
+    if grammar.line_comment.is_some_and(|token| starts_token_at(chars, index, token)) {
+        fill(kinds, index, chars.len() - index, Kind::Comment);
+        return chars.len();
+    }
+    if let Some(rule) = grammar.strings.iter().position(|rule| starts_token_at(chars, index, rule.open)) {
+        *state = State::String(rule);
+        let len = grammar.strings[rule].open.chars().count();
+        fill(kinds, index, len, Kind::String);
+        return index + len;
+    }
+    let chr = chars[index];
+    if chr == '\'' && grammar.char_literals {
+        let len = get_char_literal_len(chars, index).unwrap_or(0);
+        fill(kinds, index, len, Kind::String);
+        return index + len.max(1);
+    }
+    if is_word(chr) {
+        let len = chars[index..].iter().position(|chr| !is_word(*chr)).unwrap_or(chars.len() - index);
+        let word: String = chars[index..index + len].iter().collect();
+        let kind = if chr.is_ascii_digit() {
+            Kind::Number
+        } else if grammar.keywords.contains(&word) {
+            Kind::Keyword
+        } else if grammar.types.contains(&word) {
+            Kind::Type
+        } else if grammar.constants.contains(&word) {
+            Kind::Constant
+        } else {
+            Kind::Text
+        };
+        fill(kinds, index, len, kind);
+        return index + len;
+    }
+    kinds[index] = get_bracket_kind(chr);
+    index + 1
+}
+
+pub fn get_bracket_kind(chr: char) -> Kind {
+    match chr {
+        '[' | ']' => Kind::Bracket,
+        '{' | '}' => Kind::Brace,
+        '(' | ')' => Kind::Paren,
+        _ => Kind::Text,
+    }
+}
+
+fn is_same_language(a: Option<&Language>, b: Option<&Language>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => ptr::eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+// how far above the window the tokenizer starts when it has not been there yet
+const SYNC_LINES: usize = 2000;
+
+// the state at the start of each line down to the last one drawn; the text it was worked out from is a clone
+// sharing the rope's leaves, so after an edit only the lines from the first changed one are redone
+pub struct Highlights {
+    language: Option<&'static Language>,
+    text: LineRope,
+    states: Vec<State>,
+}
+
+impl Highlights {
+    pub fn new() -> Self {
+        Highlights {
+            language: None,
+            text: LineRope::new(),
+            states: Vec::new(),
+        }
+    }
+
+    // brings the states up to date with the text for the lines before end
+    pub fn update(&mut self, language: Option<&'static Language>, file_data: &LineRope, end: usize) {
+        if !is_same_language(self.language, language) {
+            self.language = language;
+            self.states.clear();
+        } else if let Some(y) = file_data.first_difference(&self.text) {
+            // the state at the start of the changed line only depends on the lines above it
+            self.states.truncate(y + 1);
+        }
+        self.text = file_data.clone();
+        if self.states.is_empty() {
+            self.states.push(State::Code);
+        }
+        let end = end.min(file_data.len());
+        if self.states.len() >= end {
+            return;
+        }
+        // far down a big file the lines above are taken to start in code, like after a jump to the end
+        let sync_start = end.saturating_sub(SYNC_LINES);
+        if self.states.len() < sync_start {
+            self.states.resize(sync_start, State::Code);
+        }
+        let grammar = Grammar::new(language);
+        while self.states.len() < end {
+            let y = self.states.len() - 1;
+            let (_, state) = highlight_line(&grammar, &file_data[y], self.states[y]);
+            self.states.push(state);
+        }
+    }
+
+    // the kind of each character of the lines, which update has to have reached
+    pub fn get_kinds(&self, file_data: &LineRope, lines: &[usize]) -> Vec<Vec<Kind>> {
+        let grammar = Grammar::new(self.language);
+        lines
+            .iter()
+            .map(|&y| highlight_line(&grammar, &file_data[y], self.states.get(y).copied().unwrap_or(State::Code)).0)
+            .collect()
+    }
+}
+
+pub fn get_color(kind: Kind, theme: &Theme) -> Color {
+    match kind {
+        Kind::Text => theme.fg,
+        Kind::Comment => theme.comment,
+        Kind::String => theme.string,
+        Kind::Number => theme.number,
+        Kind::Keyword => theme.keyword,
+        Kind::Type => theme.type_name,
+        Kind::Constant => theme.constant,
+        Kind::Bracket => theme.bracket,
+        Kind::Brace => theme.brace,
+        Kind::Paren => theme.paren,
+    }
+}
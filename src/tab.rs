@@ -0,0 +1,83 @@
+use crossterm::style::Color;
+use crate::buffer;
+use crate::theme::Theme;
+use crate::window;
+
+pub struct Tabs {
+    pub layouts: Vec<window::Layout>,
+    pub current_tab: usize,
+}
+
+impl Tabs {
+    pub fn new(layout: window::Layout) -> Self {
+        Tabs {
+            layouts: vec![layout],
+            current_tab: 0,
+        }
+    }
+
+    pub fn current(&mut self) -> &mut window::Layout {
+        &mut self.layouts[self.current_tab]
+    }
+
+    // the new tab goes after the current one and shows the current window's buffer
+    pub fn new_tab(&mut self) {
+        let window = self.current().current().clone();
+        self.layouts.insert(self.current_tab + 1, window::Layout::new(window));
+        self.current_tab += 1;
+        self.update_top();
+    }
+
+    pub fn close(&mut self) -> bool {
+        if self.layouts.len() == 1 {
+            return false;
+        }
+        self.layouts.remove(self.current_tab);
+        if self.current_tab >= self.layouts.len() {
+            self.current_tab = self.layouts.len() - 1;
+        }
+        self.update_top();
+        true
+    }
+
+    pub fn next(&mut self) {
+        self.current_tab = (self.current_tab + 1) % self.layouts.len();
+    }
+
+    pub fn prev(&mut self) {
+        self.current_tab = (self.current_tab + self.layouts.len() - 1) % self.layouts.len();
+    }
+
+    // the tabline takes the first row once there is more than one tab
+    fn update_top(&mut self) {
+        let top = if self.layouts.len() > 1 { 1 } else { 0 };
+        for layout in self.layouts.iter_mut() {
+            layout.top = top;
+        }
+    }
+}
+
+pub fn get_tabline(tabs: &Tabs, buffers: &[buffer::Buffer], width: usize, theme: &Theme) -> Option<Vec<(char, Color, Color, bool)>> {
+    if tabs.layouts.len() == 1 {
+        return None;
+    }
+    let mut tabline = Vec::new();
+    for (i, layout) in tabs.layouts.iter().enumerate() {
+        let buffer = &buffers[layout.windows[layout.current_window].buffer];
+        let label = format!(
+            " {} {}{} ",
+            i + 1,
+            buffer.file_name,
+            if buffer.modified { " +" } else { "" },
+        );
+        for chr in label.chars() {
+            if i == tabs.current_tab {
+                tabline.push((chr, theme.fg, theme.bg, true));
+            } else {
+                tabline.push((chr, theme.fg, theme.inactive, false));
+            }
+        }
+    }
+    tabline.resize(width, (' ', theme.fg, theme.bg, false));
+    Some(tabline)
+}
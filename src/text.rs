@@ -0,0 +1,337 @@
+use std::ops::{Index, IndexMut, Range};
+use std::rc::Rc;
+
+// leaves are split when they grow past this many lines
+const CHUNK_SIZE: usize = 1024;
+
+// the line operations the editor needs from whatever holds a document
+pub trait TextBuffer: Index<usize, Output = String> + IndexMut<usize> {
+    fn len(&self) -> usize;
+    fn insert(&mut self, index: usize, line: String);
+    fn remove(&mut self, index: usize) -> String;
+    fn remove_lines(&mut self, range: Range<usize>);
+    fn to_vec(&self) -> Vec<String>;
+}
+
+impl TextBuffer for Vec<String> {
+    fn len(&self) -> usize {
+        Vec::len(self)
+    }
+
+    fn insert(&mut self, index: usize, line: String) {
+        Vec::insert(self, index, line);
+    }
+
+    fn remove(&mut self, index: usize) -> String {
+        Vec::remove(self, index)
+    }
+
+    fn remove_lines(&mut self, range: Range<usize>) {
+        self.drain(range);
+    }
+
+    fn to_vec(&self) -> Vec<String> {
+        self.clone()
+    }
+}
+
+// a two level rope of lines: leaves of at most CHUNK_SIZE lines, shared between clones until written to,
+// so edits only shift one leaf and undo snapshots only copy the leaf pointers
+#[derive(Clone, Default)]
+pub struct LineRope {
+    chunks: Vec<Rc<Vec<String>>>,
+    starts: Vec<usize>,
+    len: usize,
+}
+
+impl LineRope {
+    pub fn new() -> Self {
+        LineRope::default()
+    }
+
+    pub fn from_lines(lines: Vec<String>) -> Self {
+        let mut rope = LineRope::new();
+        let mut lines = lines.into_iter().peekable();
+        while lines.peek().is_some() {
+            let chunk: Vec<String> = lines.by_ref().take(CHUNK_SIZE / 2).collect();
+            rope.starts.push(rope.len);
+            rope.len += chunk.len();
+            rope.chunks.push(Rc::new(chunk));
+        }
+        rope
+    }
+
+    // adds lines at the end in whole leaves, for loading a file in pieces
+    pub fn append_lines(&mut self, lines: Vec<String>) {
+        let tail = LineRope::from_lines(lines);
+        self.chunks.extend(tail.chunks);
+        let from_chunk = self.starts.len();
+        self.update_starts(from_chunk);
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &String> {
+        self.chunks.iter().flat_map(|chunk| chunk.iter())
+    }
+
+    // chunk and offset of a line, or the end of the last chunk for len
+    fn locate(&self, index: usize) -> (usize, usize) {
+        let chunk = self.starts.partition_point(|&start| start <= index).saturating_sub(1);
+        (chunk, index - self.starts.get(chunk).copied().unwrap_or(0))
+    }
+
+    fn update_starts(&mut self, from_chunk: usize) {
+        self.starts.truncate(from_chunk);
+        let mut start = match from_chunk {
+            0 => 0,
+            _ => self.starts[from_chunk - 1] + self.chunks[from_chunk - 1].len(),
+        };
+        for chunk in &self.chunks[from_chunk..] {
+            self.starts.push(start);
+            start += chunk.len();
+        }
+        self.len = start;
+    }
+
+    // first line where the two differ, skipping leaves the two still share
+    pub fn first_difference(&self, other: &LineRope) -> Option<usize> {
+        for (i, chunk) in self.chunks.iter().enumerate() {
+            let start = self.starts[i];
+            if other.starts.get(i) == Some(&start) && Rc::ptr_eq(chunk, &other.chunks[i]) {
+                continue;
+            }
+            for (offset, line) in chunk.iter().enumerate() {
+                if start + offset >= other.len || other[start + offset] != *line {
+                    return Some(start + offset);
+                }
+            }
+        }
+        if self.len != other.len {
+            Some(self.len.min(other.len))
+        } else {
+            None
+        }
+    }
+
+    // how many lines at the end the two have in common, skipping leaves the two still share
+    pub fn common_suffix(&self, other: &LineRope) -> usize {
+        let mut count = 0;
+        for (chunk, other_chunk) in self.chunks.iter().rev().zip(other.chunks.iter().rev()) {
+            if !Rc::ptr_eq(chunk, other_chunk) {
+                break;
+            }
+            count += chunk.len();
+        }
+        while count < self.len.min(other.len) && self[self.len - 1 - count] == other[other.len - 1 - count] {
+            count += 1;
+        }
+        count
+    }
+}
+
+impl TextBuffer for LineRope {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn insert(&mut self, index: usize, line: String) {
+        assert!(index <= self.len, "insert index {} out of range for {} lines", index, self.len);
+        if self.chunks.is_empty() {
+            self.chunks.push(Rc::new(Vec::new()));
+            self.starts.push(0);
+        }
+        let (chunk, offset) = self.locate(index);
+        let lines = Rc::make_mut(&mut self.chunks[chunk]);
+        lines.insert(offset, line);
+        if lines.len() > CHUNK_SIZE {
+            let second_half = lines.split_off(lines.len() / 2);
+            self.chunks.insert(chunk + 1, Rc::new(second_half));
+        }
+        self.update_starts(chunk);
+    }
+
+    fn remove(&mut self, index: usize) -> String {
+        assert!(index < self.len, "remove index {} out of range for {} lines", index, self.len);
+        let (chunk, offset) = self.locate(index);
+        let line = Rc::make_mut(&mut self.chunks[chunk]).remove(offset);
+        if self.chunks[chunk].is_empty() {
+            self.chunks.remove(chunk);
+        }
+        self.update_starts(chunk);
+        line
+    }
+
+    fn remove_lines(&mut self, range: Range<usize>) {
+        assert!(range.end <= self.len, "remove range end {} out of range for {} lines", range.end, self.len);
+        if range.is_empty() {
+            return;
+        }
+        let (first_chunk, first_offset) = self.locate(range.start);
+        let (last_chunk, last_offset) = self.locate(range.end - 1);
+        if first_chunk == last_chunk {
+            Rc::make_mut(&mut self.chunks[first_chunk]).drain(first_offset..=last_offset);
+        } else {
+            // trim the two ends and drop every leaf in between
+            Rc::make_mut(&mut self.chunks[last_chunk]).drain(..=last_offset);
+            Rc::make_mut(&mut self.chunks[first_chunk]).truncate(first_offset);
+            self.chunks.drain(first_chunk + 1..last_chunk);
+        }
+        self.chunks.retain(|chunk| !chunk.is_empty());
+        self.update_starts(first_chunk.min(self.chunks.len()));
+    }
+
+    fn to_vec(&self) -> Vec<String> {
+        self.iter().cloned().collect()
+    }
+}
+
+impl Index<usize> for LineRope {
+    type Output = String;
+
+    fn index(&self, index: usize) -> &String {
+        assert!(index < self.len, "line {} out of range for {} lines", index, self.len);
+        let (chunk, offset) = self.locate(index);
+        &self.chunks[chunk][offset]
+    }
+}
+
+impl IndexMut<usize> for LineRope {
+    fn index_mut(&mut self, index: usize) -> &mut String {
+        assert!(index < self.len, "line {} out of range for {} lines", index, self.len);
+        let (chunk, offset) = self.locate(index);
+        &mut Rc::make_mut(&mut self.chunks[chunk])[offset]
+    }
+}
+
+impl PartialEq for LineRope {
+    fn eq(&self, other: &LineRope) -> bool {
+        self.first_difference(other).is_none()
+    }
+}
+
+impl PartialEq<Vec<String>> for LineRope {
+    fn eq(&self, other: &Vec<String>) -> bool {
+        self.len == other.len() && self.iter().zip(other.iter()).all(|(a, b)| a == b)
+    }
+}
+
+impl From<Vec<String>> for LineRope {
+    fn from(lines: Vec<String>) -> Self {
+        LineRope::from_lines(lines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered(count: usize) -> Vec<String> {
+        (0..count).map(|i| i.to_string()).collect()
+    }
+
+    #[test]
+    fn splits_a_full_leaf() {
+        let mut rope = LineRope::from_lines(numbered(CHUNK_SIZE / 2));
+        for i in 0..CHUNK_SIZE {
+            rope.insert(1, format!("new {}", i));
+        }
+        assert!(rope.chunks.len() > 1);
+        assert!(rope.chunks.iter().all(|chunk| chunk.len() <= CHUNK_SIZE));
+        assert_eq!(rope.len(), CHUNK_SIZE / 2 + CHUNK_SIZE);
+        assert_eq!(rope[0], "0");
+        assert_eq!(rope[1], format!("new {}", CHUNK_SIZE - 1));
+        assert_eq!(rope[CHUNK_SIZE], "new 0");
+        assert_eq!(rope[CHUNK_SIZE + 1], "1");
+    }
+
+    #[test]
+    fn removes_across_leaves() {
+        let mut lines = numbered(CHUNK_SIZE * 3);
+        let mut rope = LineRope::from_lines(lines.clone());
+        rope.remove_lines(10..CHUNK_SIZE * 2);
+        lines.drain(10..CHUNK_SIZE * 2);
+        assert!(rope == lines);
+        rope.remove_lines(0..rope.len());
+        assert_eq!(rope.len(), 0);
+        assert!(rope.chunks.is_empty());
+        rope.insert(0, "again".to_string());
+        assert!(rope == vec!["again".to_string()]);
+    }
+
+    #[test]
+    fn matches_a_vec_through_edits() {
+        let mut lines: Vec<String> = Vec::new();
+        let mut rope = LineRope::new();
+        // a fixed pseudo random sequence of edits
+        let mut seed: u64 = 7;
+        let mut next = |max: usize| {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (seed >> 33) as usize % max.max(1)
+        };
+        for step in 0..5000 {
+            let len = lines.len();
+            match next(8) {
+                0..=3 => {
+                    let index = next(len + 1);
+                    lines.insert(index, step.to_string());
+                    TextBuffer::insert(&mut rope, index, step.to_string());
+                }
+                4 | 5 if len > 0 => {
+                    let index = next(len);
+                    assert_eq!(TextBuffer::remove(&mut rope, index), lines.remove(index));
+                }
+                6 if len > 0 => {
+                    let start = next(len);
+                    let end = (start + next(CHUNK_SIZE * 2)).min(len);
+                    lines.drain(start..end);
+                    rope.remove_lines(start..end);
+                }
+                7 if len > 0 => {
+                    let index = next(len);
+                    lines[index].push('!');
+                    rope[index].push('!');
+                }
+                _ => (),
+            }
+            assert_eq!(rope.len(), lines.len());
+        }
+        assert!(rope == lines);
+        assert_eq!(rope.to_vec(), lines);
+    }
+
+    #[test]
+    fn writes_do_not_touch_clones() {
+        let lines = numbered(CHUNK_SIZE * 2);
+        let rope = LineRope::from_lines(lines.clone());
+        let mut copy = rope.clone();
+        copy[5] = "changed".to_string();
+        TextBuffer::insert(&mut copy, CHUNK_SIZE, "inserted".to_string());
+        assert!(rope == lines);
+        assert_eq!(copy[5], "changed");
+        assert_eq!(copy[CHUNK_SIZE], "inserted");
+    }
+
+    #[test]
+    fn finds_the_changed_lines() {
+        let rope = LineRope::from_lines(numbered(CHUNK_SIZE * 3));
+        assert_eq!(rope.first_difference(&rope.clone()), None);
+        assert_eq!(rope.common_suffix(&rope.clone()), rope.len());
+        let mut changed = rope.clone();
+        changed[CHUNK_SIZE + 1] = "changed".to_string();
+        assert_eq!(rope.first_difference(&changed), Some(CHUNK_SIZE + 1));
+        assert_eq!(rope.common_suffix(&changed), rope.len() - CHUNK_SIZE - 2);
+        let mut shorter = rope.clone();
+        TextBuffer::remove(&mut shorter, rope.len() - 1);
+        assert_eq!(rope.first_difference(&shorter), Some(rope.len() - 1));
+        assert_eq!(shorter.first_difference(&rope), Some(rope.len() - 1));
+        assert_eq!(rope.common_suffix(&shorter), 0);
+    }
+
+    #[test]
+    fn appends_in_whole_leaves() {
+        let mut rope = LineRope::from_lines(numbered(3));
+        rope.append_lines(vec!["3".to_string(), "4".to_string()]);
+        rope.append_lines(Vec::new());
+        assert!(rope == numbered(5));
+        assert_eq!(rope[4], "4");
+    }
+}
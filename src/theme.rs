@@ -0,0 +1,77 @@
+use crossterm::style::Color;
+
+pub const THEME_NAMES: &[&str] = &["dark", "light"];
+
+// every colour the screen is drawn with
+#[derive(Clone, Copy)]
+pub struct Theme {
+    pub fg: Color,
+    pub bg: Color,
+    // line numbers, borders and window separators
+    pub dim: Color,
+    // background of inactive status lines and tabs
+    pub inactive: Color,
+    pub preview: Color,
+    pub recording: Color,
+    pub comment: Color,
+    pub string: Color,
+    pub number: Color,
+    pub keyword: Color,
+    pub type_name: Color,
+    pub constant: Color,
+    pub bracket: Color,
+    pub brace: Color,
+    pub paren: Color,
+    pub search_fg: Color,
+    pub search_bg: Color,
+    pub error: Color,
+    pub warning: Color,
+}
+
+pub fn get_theme(name: &str) -> Option<Theme> {
+    match name {
+        "dark" => Some(Theme {
+            fg: Color::White,
+            bg: Color::Black,
+            dim: Color::DarkGrey,
+            inactive: Color::DarkGrey,
+            preview: Color::Grey,
+            recording: Color::Red,
+            comment: Color::Green,
+            string: Color::Magenta,
+            number: Color::Red,
+            keyword: Color::Blue,
+            type_name: Color::DarkCyan,
+            constant: Color::Red,
+            bracket: Color::Green,
+            brace: Color::Cyan,
+            paren: Color::Yellow,
+            search_fg: Color::Black,
+            search_bg: Color::Green,
+            error: Color::Red,
+            warning: Color::Yellow,
+        }),
+        "light" => Some(Theme {
+            fg: Color::Black,
+            bg: Color::White,
+            dim: Color::DarkGrey,
+            inactive: Color::Grey,
+            preview: Color::DarkGrey,
+            recording: Color::DarkRed,
+            comment: Color::DarkGreen,
+            string: Color::DarkMagenta,
+            number: Color::DarkRed,
+            keyword: Color::DarkBlue,
+            type_name: Color::DarkCyan,
+            constant: Color::DarkRed,
+            bracket: Color::DarkGreen,
+            brace: Color::DarkCyan,
+            paren: Color::DarkYellow,
+            search_fg: Color::White,
+            search_bg: Color::DarkGreen,
+            error: Color::DarkRed,
+            warning: Color::DarkYellow,
+        }),
+        _ => None,
+    }
+}
@@ -0,0 +1,453 @@
+use std::ptr;
+use streaming_iterator::StreamingIterator;
+use tree_sitter::{InputEdit, Node, Parser, Point, Query, QueryCursor, Tree};
+use crate::helper;
+use crate::language::{self, Language};
+use crate::syntax::{self, Kind};
+use crate::text::{LineRope, TextBuffer};
+
+// bigger files are left to the tokenizer
+const MAX_TREE_LINES: usize = 200_000;
+
+// the first and last character of a text object as (x, y)
+type Range = ((usize, usize), (usize, usize));
+
+// the built in grammars by language name, with their highlight queries
+fn get_grammar(name: &str) -> Option<(tree_sitter::Language, String)> {
+    match name {
+        "c" => Some((tree_sitter_c::LANGUAGE.into(), tree_sitter_c::HIGHLIGHT_QUERY.to_string())),
+        "go" => Some((tree_sitter_go::LANGUAGE.into(), tree_sitter_go::HIGHLIGHTS_QUERY.to_string())),
+        "javascript" => Some((
+            tree_sitter_javascript::LANGUAGE.into(),
+            format!("{}\n{}", tree_sitter_javascript::JSX_HIGHLIGHT_QUERY, tree_sitter_javascript::HIGHLIGHT_QUERY),
+        )),
+        "python" => Some((tree_sitter_python::LANGUAGE.into(), tree_sitter_python::HIGHLIGHTS_QUERY.to_string())),
+        "rust" => Some((tree_sitter_rust::LANGUAGE.into(), tree_sitter_rust::HIGHLIGHTS_QUERY.to_string())),
+        // the tsx grammar also reads plain typescript, and its queries build on the javascript ones
+        "typescript" => Some((
+            tree_sitter_typescript::LANGUAGE_TSX.into(),
+            format!(
+                "{}\n{}\n{}",
+                tree_sitter_typescript::HIGHLIGHTS_QUERY,
+                tree_sitter_javascript::JSX_HIGHLIGHT_QUERY,
+                tree_sitter_javascript::HIGHLIGHT_QUERY,
+            ),
+        )),
+        _ => None,
+    }
+}
+
+// the highlight query capture names, like keyword.function or string.special, by the part before the dot
+fn get_capture_kind(name: &str) -> Kind {
+    match name.split('.').next().unwrap_or(name) {
+        "comment" => Kind::Comment,
+        "string" | "escape" | "character" => Kind::String,
+        "number" | "float" => Kind::Number,
+        "keyword" | "conditional" | "repeat" | "include" | "exception" => Kind::Keyword,
+        "type" | "constructor" => Kind::Type,
+        "constant" | "boolean" => Kind::Constant,
+        _ => Kind::Text,
+    }
+}
+
+fn is_same_language(a: Option<&Language>, b: Option<&Language>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => ptr::eq(a, b),
+        (None, None) => true,
+        _ => false,
+    }
+}
+
+// the parser reads the text a line at a time, with the newline the rope leaves out
+fn read_text(file_data: &LineRope, point: Point) -> &[u8] {
+    if point.row >= file_data.len() {
+        return &[];
+    }
+    let line = file_data[point.row].as_bytes();
+    if point.column < line.len() {
+        &line[point.column..]
+    } else {
+        b"\n"
+    }
+}
+
+// the bytes the lines from start to end take up with their newlines
+fn get_byte_len(file_data: &LineRope, start: usize, end: usize) -> usize {
+    (start..end).map(|y| file_data[y].len() + 1).sum()
+}
+
+// the text of a node for the query predicates, like #match? on an identifier
+fn get_node_text<'a>(file_data: &'a LineRope, node: Node) -> impl Iterator<Item = &'a [u8]> {
+    let (start, end) = (node.start_position(), node.end_position());
+    (start.row..(end.row + 1).min(file_data.len())).flat_map(move |y| {
+        let line = file_data[y].as_bytes();
+        let from = if y == start.row { start.column.min(line.len()) } else { 0 };
+        let to = if y == end.row { end.column.min(line.len()).max(from) } else { line.len() };
+        let newline: &[u8] = if y < end.row { b"\n" } else { b"" };
+        [&line[from..to], newline]
+    })
+}
+
+// the last character before a node's end, which is exclusive
+fn get_last_position(file_data: &LineRope, point: Point) -> (usize, usize) {
+    if point.column > 0 {
+        (point.column - 1, point.row)
+    } else if point.row > 0 {
+        (file_data[point.row - 1].len().saturating_sub(1), point.row - 1)
+    } else {
+        (0, 0)
+    }
+}
+
+fn contains_kind(kinds: &[String], node: Node) -> bool {
+    kinds.iter().any(|kind| kind == node.kind())
+}
+
+// the node between the delimiters of one like { ... } or ( ... ), with a line of its own dropped from either end,
+// as inclusive (x, y) positions
+fn get_inner_range(file_data: &LineRope, node: Node) -> Option<Range> {
+    let (mut start, mut end) = (node.start_position(), node.end_position());
+    let (first, last) = (node.child(0)?, node.child(node.child_count().saturating_sub(1))?);
+    if node.child_count() >= 2 && !first.is_named() && !last.is_named() {
+        start = first.end_position();
+        end = last.start_position();
+    }
+    if file_data[start.row][start.column.min(file_data[start.row].len())..].trim().is_empty() && start.row < end.row {
+        start = Point::new(start.row + 1, 0);
+    }
+    if file_data[end.row][..end.column.min(file_data[end.row].len())].trim().is_empty() && end.row > start.row {
+        end = Point::new(end.row - 1, file_data[end.row - 1].len());
+    }
+    if start >= end {
+        return None;
+    }
+    Some(((start.column, start.row), get_last_position(file_data, end)))
+}
+
+// a buffer's syntax tree, parsed again after each change reusing what did not change, and the folds closed in it
+pub struct SyntaxTree {
+    language: Option<&'static Language>,
+    parser: Parser,
+    query: Option<Query>,
+    tree: Option<Tree>,
+    text: LineRope,
+    // closed folds as first and last line, a fold inside a closed one stays closed when that one is opened
+    pub folds: Vec<(usize, usize)>,
+}
+
+impl SyntaxTree {
+    pub fn new() -> Self {
+        SyntaxTree {
+            language: None,
+            parser: Parser::new(),
+            query: None,
+            tree: None,
+            text: LineRope::new(),
+            folds: Vec::new(),
+        }
+    }
+
+    pub fn is_parsed(&self) -> bool {
+        self.tree.is_some()
+    }
+
+    // tells the old tree what lines changed since it was parsed and parses the text again
+    pub fn update(&mut self, language: Option<&'static Language>, file_data: &LineRope) {
+        if !is_same_language(self.language, language) {
+            self.language = language;
+            self.tree = None;
+            self.query = None;
+            self.folds.clear();
+            if let Some((grammar, source)) = language.and_then(|language| get_grammar(&language.name)) {
+                if self.parser.set_language(&grammar).is_ok() {
+                    self.query = Query::new(&grammar, &source).ok();
+                }
+            }
+        }
+        if self.query.is_none() || file_data.len() > MAX_TREE_LINES {
+            self.tree = None;
+            return;
+        }
+        let start = match (&self.tree, file_data.first_difference(&self.text)) {
+            (Some(_), None) => return,
+            (_, start) => start.unwrap_or(0),
+        };
+        if let Some(tree) = &mut self.tree {
+            let suffix = file_data.common_suffix(&self.text).min(file_data.len() - start).min(self.text.len() - start);
+            let (old_end, new_end) = (self.text.len() - suffix, file_data.len() - suffix);
+            let start_byte = get_byte_len(file_data, 0, start);
+            tree.edit(&InputEdit {
+                start_byte,
+                old_end_byte: start_byte + get_byte_len(&self.text, start, old_end),
+                new_end_byte: start_byte + get_byte_len(file_data, start, new_end),
+                start_position: Point::new(start, 0),
+                old_end_position: Point::new(old_end, 0),
+                new_end_position: Point::new(new_end, 0),
+            });
+            // folds move with the lines below the change and open when it touches them
+            self.folds.retain(|(fold_start, fold_end)| *fold_end < start || *fold_start >= old_end);
+            for (fold_start, fold_end) in self.folds.iter_mut() {
+                if *fold_start >= old_end {
+                    *fold_start = *fold_start + new_end - old_end;
+                    *fold_end = *fold_end + new_end - old_end;
+                }
+            }
+        }
+        self.tree = self.parser.parse_with(&mut |_, point| read_text(file_data, point), self.tree.as_ref());
+        self.text = file_data.clone();
+    }
+
+    // the kind of each character of the lines from the highlight query; a capture inside another one wins,
+    // and for the same node the first pattern that matches it does
+    pub fn get_kinds(&self, file_data: &LineRope, lines: &[usize]) -> Option<Vec<Vec<Kind>>> {
+        let (tree, query) = (self.tree.as_ref()?, self.query.as_ref()?);
+        let mut cursor = QueryCursor::new();
+        let kinds = lines
+            .iter()
+            .map(|&y| {
+                let line = &file_data[y];
+                let mut kinds = vec![Kind::Text; line.len()];
+                let mut painted = Vec::new();
+                cursor.set_point_range(Point::new(y, 0)..Point::new(y + 1, 0));
+                let mut captures = cursor.captures(query, tree.root_node(), |node: Node| get_node_text(file_data, node));
+                while let Some((query_match, index)) = captures.next() {
+                    let capture = query_match.captures[*index];
+                    let node = capture.node;
+                    if painted.contains(&node.id()) {
+                        continue;
+                    }
+                    painted.push(node.id());
+                    let kind = get_capture_kind(query.capture_names()[capture.index as usize]);
+                    let (start, end) = (node.start_position(), node.end_position());
+                    let from = if start.row < y { 0 } else { start.column.min(line.len()) };
+                    let to = if end.row > y { line.len() } else { end.column.min(line.len()) };
+                    kinds[from..to].fill(kind);
+                }
+                // brackets are colored as the tokenizer does, not every query captures them
+                line.char_indices()
+                    .map(|(index, chr)| match kinds[index] {
+                        Kind::Text => syntax::get_bracket_kind(chr),
+                        kind => kind,
+                    })
+                    .collect()
+            })
+            .collect();
+        Some(kinds)
+    }
+
+    fn get_node_at(&self, x: usize, y: usize) -> Option<Node<'_>> {
+        let point = Point::new(y, x);
+        self.tree.as_ref()?.root_node().descendant_for_point_range(point, point)
+    }
+
+    // the first and last character of a text object at the cursor: f function, c class, B block and a argument,
+    // the inner one leaving out the delimiters, or the separator for an argument
+    pub fn get_text_object(
+        &self,
+        file_data: &LineRope,
+        x: usize,
+        y: usize,
+        inner: bool,
+        object: char,
+    ) -> Result<Range, String> {
+        let language = match self.language {
+            Some(language) if self.tree.is_some() => language,
+            _ => return Err(format!("No syntax tree for {}", language::get_name(self.language))),
+        };
+        let (name, range) = match object {
+            'f' => ("function", self.get_node_object(file_data, x, y, inner, object, &language.function_nodes)),
+            'c' => ("class", self.get_node_object(file_data, x, y, inner, object, &language.class_nodes)),
+            'B' => ("block", self.get_node_object(file_data, x, y, inner, object, &language.block_nodes)),
+            'a' => ("argument", self.get_argument(file_data, x, y, inner)),
+            _ => return Err(format!("Unknown text object {}", object)),
+        };
+        range.ok_or(format!("No {} at the cursor", name))
+    }
+
+    fn get_node_object(
+        &self,
+        file_data: &LineRope,
+        x: usize,
+        y: usize,
+        inner: bool,
+        object: char,
+        kinds: &[String],
+    ) -> Option<Range> {
+        let mut node = self.get_node_at(x, y)?;
+        while !contains_kind(kinds, node) {
+            node = node.parent()?;
+        }
+        if !inner {
+            return Some(((node.start_position().column, node.start_position().row), get_last_position(file_data, node.end_position())));
+        }
+        let body = match object {
+            'B' => node,
+            _ => node.child_by_field_name("body").or_else(|| node.named_child(node.named_child_count().saturating_sub(1)))?,
+        };
+        get_inner_range(file_data, body)
+    }
+
+    // the argument or list item at the cursor, with the comma and space after it or, for the last one, before it
+    fn get_argument(&self, file_data: &LineRope, x: usize, y: usize, inner: bool) -> Option<Range> {
+        let language = self.language?;
+        let mut node = self.get_node_at(x, y)?;
+        loop {
+            let parent = node.parent()?;
+            if contains_kind(&language.argument_lists, parent) {
+                break;
+            }
+            node = parent;
+        }
+        // on a comma or bracket of the list take the argument after it, or the one before at the end
+        if !node.is_named() {
+            node = node.next_named_sibling().or_else(|| node.prev_named_sibling())?;
+        }
+        let (mut start, mut end) = (node.start_position(), node.end_position());
+        if !inner {
+            match (node.next_named_sibling(), node.prev_named_sibling()) {
+                (Some(next), _) => end = next.start_position(),
+                (None, Some(prev)) => start = prev.end_position(),
+                (None, None) => (),
+            }
+        }
+        Some(((start.column, start.row), get_last_position(file_data, end)))
+    }
+
+    // the indent for a line broken at the point: one level past the line that starts the innermost indent node
+    // around it, None outside of any so the caller falls back on the line's own indent
+    pub fn get_indent(&self, file_data: &LineRope, x: usize, y: usize, indent_width: usize) -> Option<usize> {
+        let language = self.language?;
+        let point = Point::new(y, x);
+        // from the character before the break, as a node that ends at the point does not contain it
+        let mut node = self.get_node_at(x.saturating_sub(1), y)?;
+        loop {
+            // a node that ends in a closing bracket is left at it, one that ends in a statement is not
+            let ends_open = node.child(node.child_count().saturating_sub(1)).is_some_and(|last| last.is_named());
+            let contains = node.start_position() < point && (point < node.end_position() || point == node.end_position() && ends_open);
+            if contains && contains_kind(&language.indent_nodes, node) {
+                return Some(helper::count_leading_spaces(&file_data[node.start_position().row]) + indent_width);
+            }
+            node = node.parent()?;
+        }
+    }
+
+    // the lines of the innermost function, class or block around the line that spans more than one
+    pub fn get_fold_range(&self, file_data: &LineRope, y: usize) -> Option<(usize, usize)> {
+        let language = self.language?;
+        if self.tree.is_none() || y >= file_data.len() {
+            return None;
+        }
+        // from the end of the line, where the bracket or colon that opens a block is
+        let line = file_data[y].trim_end();
+        let mut node = self.get_node_at(line.len().saturating_sub(1), y)?;
+        loop {
+            let (start, end) = (node.start_position().row, node.end_position().row);
+            let is_foldable = contains_kind(&language.function_nodes, node)
+                || contains_kind(&language.class_nodes, node)
+                || contains_kind(&language.block_nodes, node);
+            if is_foldable && start < end && start <= y && !self.folds.contains(&(start, end)) {
+                return Some((start, end));
+            }
+            node = node.parent()?;
+        }
+    }
+
+    // every function, class and block that spans more than one line
+    pub fn get_all_fold_ranges(&self) -> Vec<(usize, usize)> {
+        let (Some(language), Some(tree)) = (self.language, &self.tree) else {
+            return Vec::new();
+        };
+        let mut ranges = Vec::new();
+        let mut cursor = tree.walk();
+        let mut done = false;
+        while !done {
+            let node = cursor.node();
+            let (start, end) = (node.start_position().row, node.end_position().row);
+            let is_foldable = contains_kind(&language.function_nodes, node)
+                || contains_kind(&language.class_nodes, node)
+                || contains_kind(&language.block_nodes, node);
+            if is_foldable && start < end && !ranges.contains(&(start, end)) {
+                ranges.push((start, end));
+            }
+            if cursor.goto_first_child() || cursor.goto_next_sibling() {
+                continue;
+            }
+            loop {
+                if !cursor.goto_parent() {
+                    done = true;
+                    break;
+                }
+                if cursor.goto_next_sibling() {
+                    break;
+                }
+            }
+        }
+        ranges
+    }
+
+    // closes the innermost fold around the line that is still open, returns its first line
+    pub fn close_fold(&mut self, file_data: &LineRope, y: usize) -> Result<usize, String> {
+        let (start, end) = self.get_fold_range(file_data, y).ok_or("No fold found")?;
+        self.folds.push((start, end));
+        Ok(start)
+    }
+
+    // opens the outermost closed fold the line is in, returns its first line
+    pub fn open_fold(&mut self, y: usize) -> Result<usize, String> {
+        let (start, end) = self.get_closed_fold(y).ok_or("No fold found")?;
+        self.folds.retain(|closed| *closed != (start, end));
+        Ok(start)
+    }
+
+    // the outermost closed fold the line is in
+    pub fn get_closed_fold(&self, y: usize) -> Option<(usize, usize)> {
+        self.folds.iter().filter(|(start, end)| *start <= y && y <= *end).min_by_key(|(start, _)| *start).copied()
+    }
+
+    // the lines drawn from the top of a window down, a closed fold taking up one for its first line
+    pub fn get_visible_lines(&self, top: usize, count: usize, len: usize) -> Vec<usize> {
+        let mut lines = Vec::new();
+        let mut y = self.get_closed_fold(top).map_or(top, |(start, _)| start);
+        while lines.len() < count && y < len {
+            lines.push(y);
+            y = self.get_closed_fold(y).map_or(y, |(_, end)| end) + 1;
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> LineRope {
+        LineRope::from_lines(text.lines().map(|line| line.to_string()).collect())
+    }
+
+    #[test]
+    fn does_not_fold_without_a_tree() {
+        let file_data = lines("int main() {\n    return 0;\n}");
+        let mut tree = SyntaxTree::new();
+        assert_eq!(tree.close_fold(&file_data, 0), Err("No fold found".to_string()));
+        // a language with no grammar built in
+        tree.update(language::get_language("cpp"), &file_data);
+        assert!(!tree.is_parsed());
+        assert_eq!(tree.close_fold(&file_data, 0), Err("No fold found".to_string()));
+        assert_eq!(tree.close_fold(&file_data, 10), Err("No fold found".to_string()));
+    }
+
+    #[test]
+    fn folds_the_block_around_a_line() {
+        let file_data = lines("fn main() {\n    let x = 1;\n    x\n}\nconst X: u8 = 1;");
+        let mut tree = SyntaxTree::new();
+        tree.update(language::get_language("rust"), &file_data);
+        assert!(tree.is_parsed());
+        assert_eq!(tree.close_fold(&file_data, 0), Ok(0));
+        assert_eq!(tree.get_closed_fold(2), Some((0, 3)));
+        assert_eq!(tree.get_visible_lines(0, 10, file_data.len()), vec![0, 4]);
+        assert_eq!(tree.open_fold(2), Ok(0));
+        // a cursor past the end of the text the tree was parsed from
+        let shorter = lines("fn main() {}");
+        assert_eq!(tree.close_fold(&shorter, 3), Err("No fold found".to_string()));
+    }
+}
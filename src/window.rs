@@ -0,0 +1,537 @@
+use crossterm::execute;
+use crossterm::cursor::{SetCursorStyle, MoveTo};
+use crossterm::event::KeyCode;
+use crossterm::style::Color;
+use crossterm::terminal::size;
+use crate::buffer;
+use crate::helper;
+use crate::options;
+use crate::quickfix;
+use crate::statusline;
+use crate::text::TextBuffer;
+
+#[derive(Clone)]
+pub struct Window {
+    pub buffer: usize,
+    pub cursor_x: usize,
+    pub cursor_y: usize,
+    pub window_line_x: usize,
+    pub window_line_y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Window {
+    pub fn new(buffers: &[buffer::Buffer], index: usize) -> Self {
+        let buffer = &buffers[index];
+        Window {
+            buffer: index,
+            cursor_x: buffer.cursor_x,
+            cursor_y: buffer.cursor_y,
+            window_line_x: buffer.window_line_x,
+            window_line_y: buffer.window_line_y,
+            width: 0,
+            height: 0,
+        }
+    }
+
+    pub fn save_position(&self, buffers: &mut [buffer::Buffer]) {
+        let buffer = &mut buffers[self.buffer];
+        buffer.cursor_x = self.cursor_x;
+        buffer.cursor_y = self.cursor_y;
+        buffer.window_line_x = self.window_line_x;
+        buffer.window_line_y = self.window_line_y;
+    }
+
+    pub fn set_buffer(&mut self, buffers: &mut [buffer::Buffer], index: usize) {
+        self.save_position(buffers);
+        let (width, height) = (self.width, self.height);
+        *self = Window::new(buffers, index);
+        self.width = width;
+        self.height = height;
+    }
+}
+
+// drawn on top of the windows, e.g. by the file finder
+pub struct Overlay {
+    pub x: usize,
+    pub y: usize,
+    pub rows: Vec<Vec<(char, Color, Color, bool)>>,
+    pub cursor: (usize, usize),
+}
+
+pub enum Node {
+    Window(usize),
+    Split {
+        vertical: bool,
+        ratio: f64,
+        first: Box<Node>,
+        second: Box<Node>,
+    },
+}
+
+pub struct Layout {
+    pub windows: Vec<Window>,
+    pub root: Node,
+    pub current_window: usize,
+    pub top: usize,
+}
+
+impl Layout {
+    pub fn new(window: Window) -> Self {
+        Layout {
+            windows: vec![window],
+            root: Node::Window(0),
+            current_window: 0,
+            top: 0,
+        }
+    }
+
+    pub fn current(&mut self) -> &mut Window {
+        &mut self.windows[self.current_window]
+    }
+
+    // the new window takes the top (or left) half and becomes current
+    pub fn split(&mut self, vertical: bool) {
+        let new_window = self.windows[self.current_window].clone();
+        self.windows.push(new_window);
+        let new_index = self.windows.len() - 1;
+        replace_window(&mut self.root, self.current_window, &mut |old| Node::Split {
+            vertical,
+            ratio: 0.5,
+            first: Box::new(Node::Window(new_index)),
+            second: Box::new(old),
+        });
+        self.current_window = new_index;
+    }
+
+    // a full width window along the bottom, like the quickfix window
+    pub fn split_bottom(&mut self, window: Window, height: usize) {
+        self.windows.push(window);
+        let new_index = self.windows.len() - 1;
+        let (_, total) = get_layout_size();
+        let total = total - self.top;
+        let root = std::mem::replace(&mut self.root, Node::Window(new_index));
+        self.root = Node::Split {
+            vertical: false,
+            ratio: 1.0 - height.min(total / 2) as f64 / total.max(1) as f64,
+            first: Box::new(root),
+            second: Box::new(Node::Window(new_index)),
+        };
+        self.current_window = new_index;
+    }
+
+    pub fn split_left(&mut self, window: Window, width: usize) {
+        self.windows.push(window);
+        let new_index = self.windows.len() - 1;
+        let (total, _) = get_layout_size();
+        let root = std::mem::replace(&mut self.root, Node::Window(new_index));
+        self.root = Node::Split {
+            vertical: true,
+            ratio: width.min(total / 2) as f64 / total.max(1) as f64,
+            first: Box::new(Node::Window(new_index)),
+            second: Box::new(root),
+        };
+        self.current_window = new_index;
+    }
+
+    pub fn find_window(&self, buffer: usize) -> Option<usize> {
+        self.windows.iter().position(|window| window.buffer == buffer)
+    }
+
+    pub fn close(&mut self) -> bool {
+        self.close_window(self.current_window)
+    }
+
+    pub fn close_window(&mut self, closed: usize) -> bool {
+        if self.windows.len() == 1 {
+            return false;
+        }
+        let root = std::mem::replace(&mut self.root, Node::Window(0));
+        let (root, next) = remove_window(root, closed);
+        self.root = root.expect("Can't close last window");
+        self.windows.remove(closed);
+        renumber_windows(&mut self.root, closed);
+        let next = if closed == self.current_window { next.unwrap_or(0) } else { self.current_window };
+        self.current_window = if next > closed { next - 1 } else { next };
+        true
+    }
+
+    pub fn only(&mut self) {
+        let top = self.top;
+        let window = self.windows[self.current_window].clone();
+        *self = Layout::new(window);
+        self.top = top;
+    }
+
+    pub fn equalize(&mut self) {
+        equalize_node(&mut self.root);
+    }
+
+    pub fn resize(&mut self, vertical: bool, amount: i32) {
+        let (width, height) = get_layout_size();
+        resize_node(&mut self.root, self.current_window, vertical, amount, width, height - self.top);
+    }
+
+    pub fn get_rects(&self) -> Vec<(usize, usize, usize, usize, usize)> {
+        let (width, height) = get_layout_size();
+        let mut rects = Vec::new();
+        let mut separators = Vec::new();
+        get_rects(&self.root, 0, self.top, width, height - self.top, &mut rects, &mut separators);
+        rects
+    }
+
+    // stores the text area of each window so commands can scroll by it
+    pub fn update_sizes(&mut self) {
+        let rects = self.get_rects();
+        // every window has a status line below it
+        for (index, _, _, width, height) in rects {
+            self.windows[index].width = width;
+            self.windows[index].height = height.saturating_sub(1);
+        }
+    }
+
+    pub fn clamp_cursors(&mut self, buffers: &[buffer::Buffer], options: &options::Options) {
+        for window in self.windows.iter_mut() {
+            let file_data = &buffers[window.buffer].file_data;
+            window.cursor_y = helper::reset_cursor_end_file(file_data.len(), window.cursor_y);
+            (window.window_line_x, window.window_line_y) =
+                helper::calc_window_lines(file_data, window, helper::get_gutter_width(options.number), options.scrolloff);
+        }
+    }
+
+    // parses each window's buffer and works out the highlighting down to the bottom of it before it is drawn,
+    // moving a cursor hidden in a closed fold to the fold's first line
+    pub fn update_highlights(&mut self, buffers: &mut [buffer::Buffer]) {
+        for window in self.windows.iter_mut() {
+            let buffer = &mut buffers[window.buffer];
+            buffer.tree.update(buffer.language, &buffer.file_data);
+            window.cursor_y = buffer.tree.get_closed_fold(window.cursor_y).map_or(window.cursor_y, |(start, _)| start);
+            if !buffer.tree.is_parsed() {
+                let lines = buffer.tree.get_visible_lines(window.window_line_y, window.height, buffer.file_data.len());
+                let end = lines.last().map_or(0, |y| y + 1);
+                buffer.highlights.update(buffer.language, &buffer.file_data, end);
+            }
+        }
+    }
+
+    // moves to the window next to the cursor in the given direction
+    pub fn move_to(&mut self, direction: char) {
+        let rects = self.get_rects();
+        let window = &self.windows[self.current_window];
+        let (_, x, y, width, height) = match rects.iter().find(|rect| rect.0 == self.current_window) {
+            Some(rect) => *rect,
+            None => return,
+        };
+        let cursor_row = y + window.cursor_y.saturating_sub(window.window_line_y).min(height - 1);
+        let cursor_col = x + window.cursor_x.saturating_sub(window.window_line_x).min(width - 1);
+        let (target_x, target_y) = match direction {
+            'h' if x >= 2 => (x - 2, cursor_row),
+            'l' => (x + width + 1, cursor_row),
+            'k' if y >= 1 => (cursor_col, y - 1),
+            'j' => (cursor_col, y + height),
+            _ => return,
+        };
+        if let Some(rect) = rects.iter().find(|(_, x, y, width, height)| {
+            target_x >= *x && target_x < x + width && target_y >= *y && target_y < y + height
+        }) {
+            self.current_window = rect.0;
+        }
+    }
+
+    pub fn next(&mut self) {
+        let rects = self.get_rects();
+        if let Some(position) = rects.iter().position(|rect| rect.0 == self.current_window) {
+            self.current_window = rects[(position + 1) % rects.len()].0;
+        }
+    }
+}
+
+pub fn get_layout_size() -> (usize, usize) {
+    let (width, height) = size().unwrap_or((80, 24));
+    (width as usize, height as usize - 1)
+}
+
+fn replace_window(node: &mut Node, index: usize, f: &mut dyn FnMut(Node) -> Node) {
+    match node {
+        Node::Window(window) if *window == index => {
+            let old = std::mem::replace(node, Node::Window(index));
+            *node = f(old);
+        }
+        Node::Window(_) => (),
+        Node::Split { first, second, .. } => {
+            replace_window(first, index, f);
+            replace_window(second, index, f);
+        }
+    }
+}
+
+fn first_window(node: &Node) -> usize {
+    match node {
+        Node::Window(index) => *index,
+        Node::Split { first, .. } => first_window(first),
+    }
+}
+
+// returns the remaining tree and the window that took over the space
+fn remove_window(node: Node, index: usize) -> (Option<Node>, Option<usize>) {
+    match node {
+        Node::Window(window) if window == index => (None, None),
+        Node::Window(window) => (Some(Node::Window(window)), None),
+        Node::Split { vertical, ratio, first, second } => {
+            let (first, first_next) = remove_window(*first, index);
+            let (second, second_next) = remove_window(*second, index);
+            match (first, second) {
+                (Some(first), Some(second)) => (
+                    Some(Node::Split { vertical, ratio, first: Box::new(first), second: Box::new(second) }),
+                    first_next.or(second_next),
+                ),
+                (Some(node), None) | (None, Some(node)) => {
+                    let next = first_next.or(second_next).unwrap_or(first_window(&node));
+                    (Some(node), Some(next))
+                }
+                (None, None) => (None, None),
+            }
+        }
+    }
+}
+
+fn renumber_windows(node: &mut Node, removed: usize) {
+    match node {
+        Node::Window(index) => {
+            if *index > removed {
+                *index -= 1;
+            }
+        }
+        Node::Split { first, second, .. } => {
+            renumber_windows(first, removed);
+            renumber_windows(second, removed);
+        }
+    }
+}
+
+fn contains_window(node: &Node, index: usize) -> bool {
+    match node {
+        Node::Window(window) => *window == index,
+        Node::Split { first, second, .. } => contains_window(first, index) || contains_window(second, index),
+    }
+}
+
+fn equalize_node(node: &mut Node) {
+    if let Node::Split { ratio, first, second, .. } = node {
+        *ratio = 0.5;
+        equalize_node(first);
+        equalize_node(second);
+    }
+}
+
+fn get_split_size(vertical: bool, ratio: f64, width: usize, height: usize) -> usize {
+    if vertical {
+        let available = width.saturating_sub(1);
+        ((available as f64 * ratio).round() as usize).clamp(1, available.saturating_sub(1).max(1))
+    } else {
+        ((height as f64 * ratio).round() as usize).clamp(2.min(height), height.saturating_sub(2).max(1))
+    }
+}
+
+// adjusts the closest enclosing split of the right direction, returns true once handled
+fn resize_node(node: &mut Node, index: usize, vertical: bool, amount: i32, width: usize, height: usize) -> bool {
+    if let Node::Split { vertical: split_vertical, ratio, first, second } = node {
+        let first_size = get_split_size(*split_vertical, *ratio, width, height);
+        let in_first = contains_window(first, index);
+        let handled = match (*split_vertical, in_first) {
+            (true, true) => resize_node(first, index, vertical, amount, first_size, height),
+            (true, false) => resize_node(second, index, vertical, amount, width.saturating_sub(first_size + 1), height),
+            (false, true) => resize_node(first, index, vertical, amount, width, first_size),
+            (false, false) => resize_node(second, index, vertical, amount, width, height.saturating_sub(first_size)),
+        };
+        if handled || *split_vertical != vertical {
+            return handled;
+        }
+        let total = if vertical { width.saturating_sub(1) } else { height };
+        let amount = if in_first { amount } else { -amount };
+        let new_size = (first_size as i32 + amount).max(1) as usize;
+        *ratio = (new_size as f64 / total.max(1) as f64).clamp(0.0, 1.0);
+        return true;
+    }
+    false
+}
+
+fn get_rects(
+    node: &Node,
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    rects: &mut Vec<(usize, usize, usize, usize, usize)>,
+    separators: &mut Vec<(usize, usize, usize)>,
+    ) {
+    match node {
+        Node::Window(index) => rects.push((*index, x, y, width, height)),
+        Node::Split { vertical, ratio, first, second } => {
+            let first_size = get_split_size(*vertical, *ratio, width, height);
+            if *vertical {
+                get_rects(first, x, y, first_size, height, rects, separators);
+                separators.push((x + first_size, y, height));
+                get_rects(second, x + first_size + 1, y, width.saturating_sub(first_size + 1), height, rects, separators);
+            } else {
+                get_rects(first, x, y, width, first_size, rects, separators);
+                get_rects(second, x, y + first_size, width, height.saturating_sub(first_size), rects, separators);
+            }
+        }
+    }
+}
+
+pub fn window_command(code: KeyCode, layout: &mut Layout, message: &mut String) {
+    match code {
+        KeyCode::Char(c @ ('h' | 'j' | 'k' | 'l')) => layout.move_to(c),
+        KeyCode::Left => layout.move_to('h'),
+        KeyCode::Down => layout.move_to('j'),
+        KeyCode::Up => layout.move_to('k'),
+        KeyCode::Right => layout.move_to('l'),
+        KeyCode::Char('w') => layout.next(),
+        KeyCode::Char('s') => layout.split(false),
+        KeyCode::Char('v') => layout.split(true),
+        KeyCode::Char('c') | KeyCode::Char('q') if !layout.close() => {
+            *message = "Cannot close last window".to_string();
+        }
+        KeyCode::Char('o') => layout.only(),
+        KeyCode::Char('=') => layout.equalize(),
+        KeyCode::Char('+') => layout.resize(false, 1),
+        KeyCode::Char('-') => layout.resize(false, -1),
+        KeyCode::Char('>') => layout.resize(true, 1),
+        KeyCode::Char('<') => layout.resize(true, -1),
+        _ => (),
+    }
+}
+
+// the editor state drawn around and over the windows
+pub struct Frame<'a> {
+    pub visual_x: usize,
+    pub visual_y: usize,
+    pub mode: char,
+    pub macro_recording: bool,
+    pub prev_keys: &'a str,
+    pub tabline: Option<Vec<(char, Color, Color, bool)>>,
+    pub overlay: Option<Overlay>,
+    pub command_line: &'a str,
+}
+
+pub fn render_layout(
+    prev_view: Vec<Vec<(char, Color, Color, bool)>>,
+    layout: &Layout,
+    buffers: &[buffer::Buffer],
+    options: &options::Options,
+    quickfix: &quickfix::Quickfix,
+    frame: Frame,
+    full_render: bool,
+) -> Vec<Vec<(char, Color, Color, bool)>> {
+    let Frame { visual_x, visual_y, mode, macro_recording, prev_keys, tabline, overlay, command_line } = frame;
+    let mut stdout = helper::get_terminal_output();
+    if mode == 'i' {
+        execute!(stdout, SetCursorStyle::SteadyBar).unwrap();
+    } else {
+        execute!(stdout, SetCursorStyle::DefaultUserShape).unwrap();
+    }
+    let theme = options.get_theme();
+    let (term_width, term_height) = get_layout_size();
+    let mut screen_view = vec![vec![(' ', theme.fg, theme.bg, false); term_width]; term_height];
+    let mut rects = Vec::new();
+    let mut separators = Vec::new();
+    get_rects(&layout.root, 0, layout.top, term_width, term_height - layout.top, &mut rects, &mut separators);
+    if let Some(tabline) = tabline {
+        screen_view[0] = tabline;
+    }
+    let mut cursor = (0, 0);
+    for &(index, x, y, width, height) in rects.iter() {
+        let window = &layout.windows[index];
+        let buffer = &buffers[window.buffer];
+        let is_current = index == layout.current_window;
+        let text_height = height.saturating_sub(1);
+        let lines = buffer.tree.get_visible_lines(window.window_line_y, text_height, buffer.file_data.len());
+        let view = helper::render_file_data(
+            &buffer.highlights,
+            &buffer.tree,
+            &buffer.file_data,
+            &lines,
+            window.window_line_x,
+            window.cursor_x,
+            window.cursor_y,
+            visual_x,
+            visual_y,
+            if is_current { mode } else { 'n' },
+            buffer.search_string.clone(),
+            buffer.searching,
+            macro_recording,
+            &quickfix.get_signs(&buffer.file_name),
+            options.number,
+            &theme,
+            width,
+        );
+        for (row, line) in view.iter().enumerate() {
+            for (col, cell) in line.iter().take(width).enumerate() {
+                screen_view[y + row][x + col] = *cell;
+            }
+        }
+        if height > 0 {
+            let status = statusline::format_status(
+                &options.statusline,
+                width,
+                window,
+                buffer,
+                if is_current { Some(mode) } else { None },
+                prev_keys,
+                macro_recording,
+            );
+            let mut chars = status.chars();
+            for col in 0..width {
+                let chr = chars.next().unwrap_or(' ');
+                let chr = if chr.is_control() { ' ' } else { chr };
+                screen_view[y + height - 1][x + col] = if is_current {
+                    (chr, theme.fg, theme.bg, true)
+                } else {
+                    (chr, theme.fg, theme.inactive, false)
+                };
+            }
+        }
+        if is_current {
+            cursor = helper::get_cursor_display(&buffer.file_data, &lines, window.window_line_x, window.cursor_x, window.cursor_y, helper::get_gutter_width(options.number));
+            cursor = (x + cursor.0, y + cursor.1);
+        }
+    }
+    for (x, y, height) in separators {
+        for line in screen_view.iter_mut().skip(y).take(height) {
+            line[x] = ('│', theme.dim, theme.bg, false);
+        }
+    }
+    if let Some(overlay) = overlay {
+        for (row, line) in overlay.rows.iter().enumerate() {
+            for (col, cell) in line.iter().enumerate() {
+                if overlay.y + row < term_height && overlay.x + col < term_width {
+                    screen_view[overlay.y + row][overlay.x + col] = *cell;
+                }
+            }
+        }
+        cursor = (overlay.x + overlay.cursor.0, overlay.y + overlay.cursor.1);
+    }
+    // a listing taller than the terminal, like a long :messages, shows its last lines
+    let command_lines: Vec<&str> = command_line.split('\n').collect();
+    let shown = command_lines.len().min(term_height + 1);
+    screen_view.resize(term_height + 1, Vec::new());
+    let first_row = term_height + 1 - shown;
+    for (i, command_line) in command_lines[command_lines.len() - shown..].iter().enumerate() {
+        let mut line_render: Vec<(char, Color, Color, bool)> = command_line
+            .chars()
+            .take(term_width)
+            .map(|chr| (chr, theme.fg, theme.bg, false))
+            .collect();
+        line_render.resize(term_width, (' ', theme.fg, theme.bg, false));
+        screen_view[first_row + i] = line_render;
+    }
+    helper::update_terminal(&prev_view, &screen_view, full_render);
+    if mode == ':' || mode == '/' {
+        cursor = (command_line.len(), term_height);
+    }
+    execute!(stdout, MoveTo(cursor.0 as u16, cursor.1 as u16)).expect("Failed to move cursor");
+    screen_view
+}